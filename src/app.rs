@@ -16,28 +16,86 @@
  * this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::error;
-use std::collections::HashMap;
+use std::{
+    cell::Cell,
+    error,
+    time::{Duration, Instant},
+};
+use json::JsonValue;
 use tui:: {
     backend::Backend,
-    layout::Alignment,
-    style::{Color, Style},
+    layout::{Alignment, Rect},
+    style::{Color, Modifier, Style},
     terminal::Frame,
+    text::{Span, Spans, Text},
     widgets::{Block, Borders, Paragraph, List, ListState, ListItem},
 };
-use crate::client::{DataHolder, Server};
+use crate::client::{create_filter, join_names, join_room, leave_room, login,
+    messages, redact_event, send_reaction, set_read_markers, set_typing, whoami,
+    ClientError,
+    ClientResult, DataHolder, FileKind, Message, MessageContent, Presence,
+    Server, StateChange};
+use crate::command::{run_slash_command, send_text, CommandLine};
+use crate::config::Config;
+use crate::emoji::EmojiPicker;
+use crate::html;
+use crate::session::Session;
+use crate::sync::{apply_messages, apply_sync};
+use crate::text::{color_mentions, day_separator, message_time, wrap};
 
 /// Application result type.
 pub type AppResult<T> = std::result::Result<T, Box<dyn error::Error>>;
 
 /// Modes the app can be on. Valid modes are:
+///     * Login - the user is entering their credentials.
 ///     * Normal - the app is taking commands.
 ///     * Insert - Allows to enter text.
+///     * Command - A command is being typed after `:`.
+///     * React - An emoji is being chosen to react to a message.
 pub enum AppMode {
+    Login,
     Normal,
     Insert,
+    Command,
+    React,
+}
+
+/// Number of editable fields in the login form.
+pub const LOGIN_FIELD_COUNT: usize = 3;
+
+/// Data entered in the login screen.
+#[derive(Default)]
+pub struct LoginForm {
+    pub server: String,
+    pub user: String,
+    pub password: String,
+
+    /// Field being edited: 0 for server, 1 for user and 2 for password.
+    pub selected_field: usize,
+    /// Error returned by the last login attempt, empty if there was none.
+    pub error: String,
+}
+
+impl LoginForm {
+    /// Returns the field currently being edited.
+    pub fn selected_mut(&mut self) -> &mut String {
+        match self.selected_field {
+            0 => &mut self.server,
+            1 => &mut self.user,
+            _ => &mut self.password,
+        }
+    }
 }
 
+/// Number of older messages requested when scrolling past the first one.
+const HISTORY_PAGE: u32 = 50;
+
+/// Milliseconds the typing notice lasts in the server.
+const TYPING_TIMEOUT: u64 = 30_000;
+/// The typing notice is sent again after this time if the user is still
+/// typing, before it expires.
+const TYPING_RESEND: Duration = Duration::from_secs(20);
+
 /// Window to show data on screen.
 pub struct MessageWindow {
     pub selected_room_id: String,
@@ -45,6 +103,39 @@ pub struct MessageWindow {
 
     pub written_msg: String,
     pub selected_char: usize,
+
+    /// Lines scrolled up from the last message. Rendering moves it to keep
+    /// the selected message on screen.
+    pub scroll: Cell<usize>,
+    /// Lines that can be scrolled, updated when rendering.
+    pub max_scroll: Cell<usize>,
+    /// Last message read when the room was opened, the new messages divider
+    /// is shown after it.
+    pub read_marker: String,
+
+    /// Event ID of the message under the cursor.
+    pub selected_msg: String,
+    /// Message the written one replies to.
+    pub reply_to: String,
+    /// Message the written one replaces.
+    pub editing: String,
+}
+
+impl Default for MessageWindow {
+    fn default() -> Self {
+        Self {
+            selected_room_id: String::new(),
+            selected_room: 0,
+            written_msg: String::new(),
+            selected_char: 1,
+            scroll: Cell::new(0),
+            max_scroll: Cell::new(0),
+            read_marker: String::new(),
+            selected_msg: String::new(),
+            reply_to: String::new(),
+            editing: String::new(),
+        }
+    }
 }
 
 /// Application.
@@ -54,98 +145,593 @@ pub struct App {
     pub selected_room: usize,
     pub selected_room_id: String,
     pub mode: AppMode,
+    pub login: LoginForm,
+    /// Last error, shown in the status bar.
+    pub error: String,
+    /// Information asked for by the user, shown in the status bar.
+    pub message: String,
+    /// Set when the background sync has to be (re)started.
+    pub needs_sync: bool,
+    /// First key of a two key command, like `gg`.
+    pub pending_key: Option<char>,
+    /// Height of the message lists, updated when rendering.
+    pub page_height: u16,
 
-    pub left_panel_width: u16,
     pub invites_height: u16,
+    /// The cursor is on the invites panel instead of the room list.
+    pub invites_focused: bool,
+    pub selected_invite: usize,
 
     pub written_msg: String,
     pub selected_char: usize,
 
     pub selected_window: usize,
     pub windows: Vec<MessageWindow>,
+
+    /// Line shown in Command mode.
+    pub command: CommandLine,
+    /// Emoji picker shown in React mode.
+    pub emoji: EmojiPicker,
+    /// Presence of the user, sent with every sync.
+    pub presence: Presence,
+
+    /// Room the server was told the user is typing in, and when.
+    typing_room: String,
+    typing_sent: Instant,
+
+    pub config: Config,
 }
 
 impl Default for App {
     fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
+
+impl App {
+    /// Constructs a new instance of [`App`].
+    pub fn new(config: Config) -> Self {
         Self {
-            holder: DataHolder {
-                server: Server{
-                    address: String::from("YOUR-SERVER"),
-                },
-                rooms: HashMap::new(),
-                users: HashMap::new(),
-                room_invites: vec![],
-                token: String::new(),
-                user_id: String::from("YOUR-USER"),
-                next_batch: String::new(),
-            },
+            holder: DataHolder::new(Server {
+                address: config.homeserver.clone(),
+            }),
             running: true,
             selected_room: 0,
             selected_room_id: String::new(),
-            mode: AppMode::Normal,
-            left_panel_width: 0,
-            invites_height: 10,
+            mode: AppMode::Login,
+            login: LoginForm {
+                server: config.homeserver.clone(),
+                ..LoginForm::default()
+            },
+            error: String::new(),
+            message: String::new(),
+            needs_sync: false,
+            pending_key: None,
+            page_height: 0,
+            invites_height: config.invites_height,
+            invites_focused: false,
+            selected_invite: 0,
 
             written_msg: String::new(),
             selected_char: 0,
 
             selected_window: 0,
-            windows: vec![MessageWindow {
-                selected_room: 0,
-                selected_room_id: String::new(),
-                written_msg: String::new(),
-                selected_char: 1,
-            }],
-        }
-    }
-}
+            windows: vec![MessageWindow::default()],
 
-impl App {
-    /// Constructs a new instance of [`App`].
-    pub fn new() -> Self {
-        Self::default()
+            command: CommandLine::default(),
+            emoji: EmojiPicker::default(),
+            presence: config.presence,
+
+            typing_room: String::new(),
+            typing_sent: Instant::now(),
+
+            config,
+        }
     }
 
     /// Handles the tick event of the terminal.
     pub fn tick(&self) {}
 
     pub fn add_window(&mut self) {
-        let window = MessageWindow {
-            selected_room: 0,
-            selected_room_id: String::new(),
-            written_msg: String::new(),
-            selected_char: 1,
+        self.windows.push(MessageWindow::default());
+    }
+
+    /// Goes back to the room list from a room. From the room list the window
+    /// is closed, and the app when it is the last one.
+    pub fn close_window(&mut self) {
+        let window = &mut self.windows[self.selected_window];
+        if !window.selected_room_id.is_empty() {
+            window.written_msg = String::new();
+            window.selected_room_id = String::new();
+            window.scroll.set(0);
+            window.selected_msg = String::new();
+            window.reply_to = String::new();
+            window.editing = String::new();
+        } else if self.windows.len() > 1 {
+            self.windows.remove(self.selected_window);
+            if self.selected_window != 0 {
+                self.selected_window -= 1;
+            }
+        } else {
+            self.running = false;
+        }
+    }
+
+    /// Scrolls the selected window up. Going past the oldest loaded message
+    /// requests older ones from the server.
+    ///
+    /// Scrolling hides the message cursor, which would keep the view on the
+    /// selected message otherwise.
+    pub fn scroll_up(&mut self, lines: usize) {
+        let window = &mut self.windows[self.selected_window];
+        window.selected_msg = String::new();
+        let max_scroll = window.max_scroll.get();
+        if window.scroll.get() >= max_scroll {
+            window.scroll.set(max_scroll);
+            self.load_older_messages();
+        } else {
+            window.scroll.set((window.scroll.get() + lines).min(max_scroll));
+        }
+    }
+
+    /// Scrolls the selected window down.
+    pub fn scroll_down(&mut self, lines: usize) {
+        let window = &mut self.windows[self.selected_window];
+        window.selected_msg = String::new();
+        window.scroll.set(window.scroll.get().saturating_sub(lines));
+    }
+
+    /// Moves the message cursor of the selected window to an older message
+    /// or to a newer one. Moving past the newest message hides the cursor.
+    pub fn move_selection(&mut self, older: bool) {
+        let window = &self.windows[self.selected_window];
+        let room = match self.holder.rooms.get(&window.selected_room_id) {
+            Some(room) => room,
+            None => return,
+        };
+        let shown: Vec<&String> = room.messages.iter()
+            .filter(|msg| self.config.show_state_events
+                || !matches!(msg.content, MessageContent::State(_)))
+            .map(|msg| &msg.event_id)
+            .collect();
+
+        let pos = shown.iter().position(|id| **id == window.selected_msg);
+        let selected = match (pos, older) {
+            (None, true) => shown.last().map(|id| id.to_string()),
+            (Some(0), true) => {
+                self.load_older_messages();
+                return;
+            }
+            (Some(i), true) => Some(shown[i - 1].to_string()),
+            (Some(i), false) if i + 1 < shown.len() => {
+                Some(shown[i + 1].to_string())
+            }
+            _ => None,
         };
+        self.windows[self.selected_window].selected_msg = selected
+            .unwrap_or_default();
+    }
+
+    /// Message under the cursor of the selected window.
+    pub fn selected_message(&self) -> Option<&Message> {
+        let window = &self.windows[self.selected_window];
+        if window.selected_msg.is_empty() {
+            return None;
+        }
+        self.holder.rooms.get(&window.selected_room_id)?.messages.iter()
+            .find(|msg| msg.event_id == window.selected_msg)
+    }
 
-        self.windows.push(window);
+    /// Starts writing a reply to the selected message.
+    pub fn reply_to_selected(&mut self) {
+        let event_id = match self.selected_message() {
+            Some(msg) => msg.event_id.clone(),
+            None => return,
+        };
+        let window = &mut self.windows[self.selected_window];
+        window.reply_to = event_id;
+        window.editing = String::new();
+        window.selected_msg = String::new();
+        self.mode = AppMode::Insert;
+    }
+
+    /// Puts the text of the selected message in the input bar to edit it.
+    /// Only text messages sent by the user can be edited.
+    pub fn edit_selected(&mut self) {
+        let (event_id, body) = match self.selected_message() {
+            Some(msg) if msg.sender == self.holder.user_id => {
+                match &msg.content {
+                    MessageContent::Text(body)
+                        | MessageContent::Notice(body)
+                        | MessageContent::Emote(body) => {
+                        (msg.event_id.clone(), body.clone())
+                    }
+                    _ => {
+                        self.error = String::from("Only text messages can \
+                            be edited");
+                        return;
+                    }
+                }
+            }
+            Some(_) => {
+                self.error = String::from("Only your messages can be edited");
+                return;
+            }
+            None => return,
+        };
+
+        let window = &mut self.windows[self.selected_window];
+        window.selected_char = body.len() + 1;
+        window.written_msg = body;
+        window.editing = event_id;
+        window.reply_to = String::new();
+        window.selected_msg = String::new();
+        self.mode = AppMode::Insert;
+    }
+
+    /// Deletes the selected message.
+    pub fn redact_selected(&mut self) {
+        let (room_id, event_id) = match self.selected_message() {
+            Some(msg) => (msg.room.clone(), msg.event_id.clone()),
+            None => return,
+        };
+        let res = redact_event(&self.holder.server, &self.holder.token,
+            &room_id, &event_id);
+        if self.report(res).is_some() {
+            self.windows[self.selected_window].selected_msg = String::new();
+        }
+    }
+
+    /// Opens the emoji picker to react to the selected message.
+    pub fn start_reaction(&mut self) {
+        match self.selected_message().map(|msg| &msg.content) {
+            Some(MessageContent::State(_)) | Some(MessageContent::Redacted) => {
+                self.error = String::from("Cannot react to this message");
+            }
+            Some(_) => {
+                self.emoji.clear();
+                self.mode = AppMode::React;
+            }
+            None => {}
+        }
+    }
+
+    /// Reacts to the selected message with a key. Reacting again with the
+    /// same key removes the reaction.
+    pub fn react(&mut self, key: &str) {
+        let msg = match self.selected_message() {
+            Some(msg) => msg,
+            None => return,
+        };
+        let room_id = msg.room.clone();
+        let own = msg.reactions.iter()
+            .find(|r| r.key == key && r.sender == self.holder.user_id)
+            .map(|r| r.event_id.clone());
+
+        let res = match own {
+            Some(event_id) => redact_event(&self.holder.server,
+                &self.holder.token, &room_id, &event_id),
+            None => send_reaction(&self.holder.server, &self.holder.token,
+                &room_id, &msg.event_id, key),
+        };
+        self.report(res);
+    }
+
+    /// Requests the messages before the oldest one in the selected room.
+    pub fn load_older_messages(&mut self) {
+        let room_id = self.windows[self.selected_window].selected_room_id
+            .clone();
+        let from = match self.holder.rooms.get(&room_id) {
+            Some(room_data) if !room_data.prev_batch.is_empty() => {
+                room_data.prev_batch.clone()
+            }
+            _ => return,
+        };
+
+        let res = messages(&self.holder.server, &self.holder.token, &room_id,
+            &from, HISTORY_PAGE);
+        if let Some(res) = self.report(res) {
+            apply_messages(&mut self.holder, &room_id, &res);
+        }
     }
 
     pub fn sel_room(&mut self) {
-        let mut window = &mut self.windows[self.selected_window];
-        let room_list: Vec<&String> = self.holder.rooms.keys().clone()
-            .collect();    
-        if window.selected_room_id == "" {
-            window.selected_room_id = room_list[window.selected_room].clone();
+        let window = &mut self.windows[self.selected_window];
+        let room_list = self.holder.sorted_rooms();
+        if window.selected_room_id.is_empty()
+            && window.selected_room < room_list.len() {
+            let room_id = room_list[window.selected_room];
+            window.selected_room_id = room_id.clone();
+            window.read_marker = self.holder.rooms[room_id]
+                .account_data.get("m.fully_read")
+                .and_then(|marker| marker["event_id"].as_str())
+                .unwrap_or("").to_string();
+        }
+    }
+
+    /// Marks the room of the selected window as read once its newest message
+    /// is on screen.
+    pub fn mark_read(&mut self) {
+        if let AppMode::Login = self.mode {
+            return;
+        }
+        let window = &self.windows[self.selected_window];
+        if window.scroll.get() > 0 {
+            return;
+        }
+
+        let room_id = window.selected_room_id.clone();
+        let user_id = self.holder.user_id.clone();
+        let room = match self.holder.rooms.get_mut(&room_id) {
+            Some(room) => room,
+            None => return,
+        };
+        let event_id = match room.messages.last() {
+            Some(msg) => msg.event_id.clone(),
+            None => return,
+        };
+        if room.receipts.get(&user_id) == Some(&event_id) {
+            return;
+        }
+
+        // Updated before sending so a failure is not retried on every frame.
+        room.receipts.insert(user_id, event_id.clone());
+        room.account_data.insert(String::from("m.fully_read"),
+            json::object! { "event_id": &event_id[..] });
+        room.unread_msgs = 0;
+        room.highlight_msgs = 0;
+
+        let res = set_read_markers(&self.holder.server, &self.holder.token,
+            &room_id, &event_id);
+        self.report(res);
+    }
+
+    /// Joins the room of the selected invite, or rejects the invite.
+    pub fn answer_invite(&mut self, accept: bool) {
+        let room_id = match self.holder.room_invites.get(self.selected_invite) {
+            Some(room_id) => room_id.clone(),
+            None => return,
+        };
+
+        let res = if accept {
+            join_room(&self.holder.server, &self.holder.token, &room_id)
+        } else {
+            leave_room(&self.holder.server, &self.holder.token, &room_id)
+        };
+
+        // The next sync would remove it too, but it can take a while.
+        if self.report(res).is_some() {
+            self.holder.room_invites.retain(|id| *id != room_id);
+            self.holder.invited_rooms.remove(&room_id);
+            let invite_count = self.holder.room_invites.len();
+            self.selected_invite = self.selected_invite
+                .min(invite_count.saturating_sub(1));
+            if invite_count == 0 {
+                self.invites_focused = false;
+            }
+        }
+    }
+
+    /// Tells the server if the user is typing a message in the selected
+    /// window, without repeating it more often than needed.
+    pub fn update_typing(&mut self) {
+        let window = &self.windows[self.selected_window];
+        let typing_room = match self.mode {
+            // Commands are not messages.
+            AppMode::Insert if !window.written_msg.is_empty()
+                && !window.written_msg.starts_with('/') => {
+                window.selected_room_id.clone()
+            }
+            _ => String::new(),
+        };
+
+        if !self.typing_room.is_empty() && self.typing_room != typing_room {
+            let res = set_typing(&self.holder.server, &self.holder.token,
+                &self.typing_room, &self.holder.user_id, false, 0);
+            self.typing_room = String::new();
+            self.report(res);
+        }
+
+        if typing_room.is_empty() || (self.typing_room == typing_room
+            && self.typing_sent.elapsed() < TYPING_RESEND) {
+            return;
+        }
+        let res = set_typing(&self.holder.server, &self.holder.token,
+            &typing_room, &self.holder.user_id, true, TYPING_TIMEOUT);
+        self.typing_room = typing_room;
+        self.typing_sent = Instant::now();
+        self.report(res);
+    }
+
+    /// Logs in with the data from the login form.
+    ///
+    /// On failure the error sent by the server is stored in the form so it
+    /// can be shown to the user, who can then try again.
+    pub fn submit_login(&mut self) {
+        let mut address = self.login.server.trim().trim_end_matches('/')
+            .to_string();
+        if !address.contains("://") {
+            address = ["https://", &address].join("");
+        }
+        self.holder.server.address = address;
+
+        let res = login(&self.holder.server, self.login.user.trim(),
+            &self.login.password, &self.holder.device_id);
+        self.login.password = String::new();
+
+        match res {
+            Ok(res) if res["access_token"].is_string() => {
+                // Nothing from a session of another user can be reused.
+                if res["user_id"] != self.holder.user_id[..] {
+                    let server = self.holder.server.clone();
+                    self.holder = DataHolder::new(server);
+                }
+                self.holder.token = res["access_token"].to_string();
+                self.holder.user_id = res["user_id"].to_string();
+                self.holder.device_id = res["device_id"].to_string();
+                self.login.error = String::new();
+                self.mode = AppMode::Normal;
+                self.needs_sync = true;
+                let _ = Session::from_holder(&self.holder).save();
+            }
+            Ok(_) => {
+                self.login.error = String::from("Unexpected answer from \
+                    server");
+            }
+            Err(e) => {
+                self.login.error = e.to_string();
+                self.login.selected_field = 2;
+            }
+        }
+    }
+
+    /// Resumes the session stored by a previous run if its token is still
+    /// valid. Otherwise the login form is filled with the stored data so the
+    /// user only has to type the password.
+    pub fn resume_session(&mut self) {
+        let session = match Session::load() {
+            Some(session) => session,
+            None => return,
+        };
+        session.restore(&mut self.holder);
+
+        match whoami(&self.holder.server, &self.holder.token) {
+            Ok(res) if res["user_id"] == self.holder.user_id[..] => {
+                self.mode = AppMode::Normal;
+                self.needs_sync = true;
+                return;
+            }
+            // Can't reach the server, keep the session and try later.
+            Err(e @ ClientError::Transport(_)) | Err(e @ ClientError::Http(_))
+                | Err(e @ ClientError::Decode(_)) => {
+                self.mode = AppMode::Normal;
+                self.needs_sync = true;
+                self.error = e.to_string();
+                return;
+            }
+            Err(e) if e.errcode() == Some("M_UNKNOWN_TOKEN") => {
+                let _ = Session::remove();
+                self.login.error = String::from("Session expired, log in \
+                    again");
+            }
+            Err(e) => {
+                self.login.error = e.to_string();
+            }
+            Ok(_) => {
+                self.login.error = String::from("Session belongs to another \
+                    user");
+            }
+        }
+        self.holder.token = String::new();
+        self.login.server = session.homeserver;
+        self.login.user = session.user_id;
+        self.login.selected_field = 2;
+    }
+
+    /// Uploads the sync filter if it was not uploaded before. If it fails the
+    /// sync is done without a filter.
+    pub fn prepare_sync(&mut self) {
+        if !self.holder.filter_id.is_empty() {
+            return;
+        }
+
+        let res = create_filter(&self.holder.server, &self.holder.token,
+            &self.holder.user_id);
+        if let Some(filter_id) = self.report(res) {
+            self.holder.filter_id = filter_id;
+            let _ = Session::from_holder(&self.holder).save();
+        }
+    }
+
+    /// Handles the answer of a background sync. If the server no longer
+    /// accepts the access token the login screen is shown again.
+    pub fn handle_sync(&mut self, res: ClientResult<JsonValue>) {
+        match res {
+            Ok(res) => {
+                // The order of the rooms can change, keep the cursors on the
+                // same rooms.
+                let cursors: Vec<Option<String>> = {
+                    let room_list = self.holder.sorted_rooms();
+                    self.windows.iter()
+                        .map(|window| room_list.get(window.selected_room)
+                            .map(|room_id| room_id.to_string()))
+                        .collect()
+                };
+
+                apply_sync(&mut self.holder, &res);
+
+                let room_list = self.holder.sorted_rooms();
+                for (window, cursor) in self.windows.iter_mut().zip(cursors) {
+                    let pos = cursor.and_then(|cursor| room_list.iter()
+                        .position(|room_id| **room_id == cursor));
+                    window.selected_room = pos.unwrap_or(window.selected_room)
+                        .min(room_list.len().saturating_sub(1));
+                }
+            }
+            Err(e) if e.errcode() == Some("M_UNKNOWN_TOKEN") => {
+                let _ = Session::remove();
+                self.holder.token = String::new();
+                self.login.server = self.holder.server.address.clone();
+                self.login.user = self.holder.user_id.clone();
+                self.login.selected_field = 2;
+                self.login.error = String::from("Session expired, log in \
+                    again");
+                self.mode = AppMode::Login;
+            }
+            Err(e) => {
+                self.error = e.to_string();
+            }
+        }
+    }
+
+    /// Shows the error of a failed request in the status bar.
+    pub fn report<T>(&mut self, res: ClientResult<T>) -> Option<T> {
+        match res {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.error = e.to_string();
+                None
+            }
         }
     }
 
     /// Renders the user interface widgets.
     pub fn render<B: Backend>(&mut self, frame: &mut Frame<'_, B>) {
-        let mut room_list: Vec<&String> = self.holder.rooms.keys().clone()
-            .collect();
-        //room_list.sort();
-        self.selected_room_id = room_list[self.selected_room].clone();
+        if let AppMode::Login = self.mode {
+            self._render_login(frame);
+            return;
+        }
+
+        let room_list = self.holder.sorted_rooms();
+        if self.selected_room < room_list.len() {
+            self.selected_room_id = room_list[self.selected_room].clone();
+        }
 
         // Messages.
+        self.page_height = frame.size().height.saturating_sub(4);
         self._render_messages(frame, &room_list);
 
         // LOWER BAR
+        let status = if !self.error.is_empty() {
+            Paragraph::new(&self.error[..])
+                .style(Style::default()
+                    .fg(Color::White)
+                    .bg(Color::Red)
+                    .add_modifier(Modifier::BOLD))
+        } else if !self.message.is_empty() {
+            Paragraph::new(&self.message[..])
+                .style(Style::default()
+                    .fg(self.config.colors.status_fg)
+                    .bg(self.config.colors.status_bg))
+        } else {
+            Paragraph::new(["Logged in as", &self.holder.user_id].join(" "))
+                .style(Style::default()
+                    .fg(self.config.colors.status_fg)
+                    .bg(self.config.colors.status_bg))
+        };
         frame.render_widget(
-            Paragraph::new("Logged in as [USERNAME]")
+            status
                 .block(Block::default().borders(Borders::NONE))
-                    .style(Style::default()
-                        .fg(Color::Black)
-                        .bg(Color::White))
                     .alignment(Alignment::Left),
             tui::layout::Rect {
                 x: 0,
@@ -162,8 +748,8 @@ impl App {
             Paragraph::new("Determinant Alpha 0.1")
                 .block(Block::default().borders(Borders::NONE))
                     .style(Style::default()
-                        .fg(Color::Black)
-                        .bg(Color::White))
+                        .fg(self.config.colors.status_fg)
+                        .bg(self.config.colors.status_bg))
                     .alignment(Alignment::Right),
             tui::layout::Rect {
                 x: frame.size().width/2,
@@ -182,8 +768,54 @@ impl App {
                     Paragraph::new("-- INSERT --")
                         .block(Block::default().borders(Borders::NONE))
                         .style(Style::default()
-                            .fg(Color::Black)
-                            .bg(Color::Green))
+                            .fg(self.config.colors.status_fg)
+                            .bg(self.config.colors.insert_bg))
+                        .alignment(Alignment::Left),
+                    tui::layout::Rect {
+                        x: 0,
+                        y: frame.size().height - 1,
+                        width: frame.size().width,
+                        height: 1,
+                    },
+                );
+            }
+
+            AppMode::Command => {
+                frame.render_widget(
+                    Paragraph::new([":", &self.command.text].join(""))
+                        .block(Block::default().borders(Borders::NONE))
+                        .style(Style::default()
+                            .fg(self.config.colors.text))
+                        .alignment(Alignment::Left),
+                    tui::layout::Rect {
+                        x: 0,
+                        y: frame.size().height - 1,
+                        width: frame.size().width,
+                        height: 1,
+                    },
+                );
+            }
+
+            AppMode::React => {
+                let mut spans = vec![
+                    Span::raw(["react: ", &self.emoji.query, "  "].join("")),
+                ];
+                for (i, (name, emoji)) in self.emoji.matches().iter()
+                    .enumerate() {
+                    let style = if i == self.emoji.selected {
+                        Style::default().add_modifier(Modifier::REVERSED)
+                    } else {
+                        Style::default()
+                    };
+                    spans.push(Span::styled([*emoji, " ", name].join(""),
+                        style));
+                    spans.push(Span::raw(" "));
+                }
+                frame.render_widget(
+                    Paragraph::new(Spans::from(spans))
+                        .block(Block::default().borders(Borders::NONE))
+                        .style(Style::default()
+                            .fg(self.config.colors.text))
                         .alignment(Alignment::Left),
                     tui::layout::Rect {
                         x: 0,
@@ -194,48 +826,154 @@ impl App {
                 );
             }
 
-            AppMode::Normal => {
+            AppMode::Normal | AppMode::Login => {
+            }
+        }
+    }
+
+    /// Show the login form.
+    fn _render_login<B: Backend>(&self, frame: &mut Frame<'_, B>) {
+        let size = frame.size();
+        let width = size.width.min(60);
+        let height = 11.min(size.height);
+        let area = Rect {
+            x: (size.width - width)/2,
+            y: (size.height - height)/2,
+            width,
+            height,
+        };
+
+        frame.render_widget(Paragraph::new("").block(Block::default()
+                .title(Span::styled("Log in to Matrix", Style::default()
+                    .fg(Color::Black)))
+                .borders(Borders::ALL)
+                .border_style(Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::White))),
+            area);
+
+        let password = "*".repeat(self.login.password.chars().count());
+        let fields = [
+            ("Homeserver", &self.login.server[..]),
+            ("User ID", &self.login.user[..]),
+            ("Password", &password[..]),
+        ];
+        for (field_i, (label, value)) in fields.iter().enumerate() {
+            let style = if field_i == self.login.selected_field {
+                Style::default().fg(Color::Black).bg(Color::White)
+            } else {
+                Style::default().fg(self.config.colors.text)
+            };
+
+            let y = area.y + 2 + 2*field_i as u16;
+            if y >= area.y + area.height - 1 {
+                break;
             }
+            frame.render_widget(Paragraph::new([label, ": ", value].join(""))
+                    .style(style),
+                Rect {
+                    x: area.x + 2,
+                    y,
+                    width: area.width.saturating_sub(4),
+                    height: 1,
+                });
+        }
+
+        // Error from the last attempt, if any.
+        if !self.login.error.is_empty() && area.height > 9 {
+            frame.render_widget(Paragraph::new(&self.login.error[..])
+                    .style(Style::default()
+                        .fg(Color::Red)
+                        .add_modifier(Modifier::BOLD)),
+                Rect {
+                    x: area.x + 2,
+                    y: area.y + 8,
+                    width: area.width.saturating_sub(4),
+                    height: 1,
+                });
         }
+
+        frame.render_widget(
+            Paragraph::new("Tab: next field  Enter: log in  Esc: quit")
+                .style(Style::default()
+                    .fg(self.config.colors.status_fg)
+                    .bg(self.config.colors.status_bg))
+                .alignment(Alignment::Left),
+            Rect {
+                x: 0,
+                y: size.height - 1,
+                width: size.width,
+                height: 1,
+            },
+        );
     }
 
     /// Show rooms list.
     fn _render_room_list<B: Backend>(&self, frame: &mut Frame<'_, B>,
-        room_list: &Vec<&String>, window_i: i32, window_x: u16, window_w: u16,
+        room_list: &[&String], window_i: i32, window_x: u16, window_w: u16,
         window_borders: Borders) {
 
         let mut items: Vec<ListItem> = vec![];
-        for room in room_list {
-            let members = &self.holder.rooms[&room[..]].members;
-            if members.len() == 2 {
-                if members[0] == self.holder.user_id {
-                    items.push(ListItem::new(self.holder.users.get(&members[1])
-                        .unwrap().name.clone()));
-                } else {
-                    items.push(ListItem::new(self.holder.users.get(&members[0])
-                        .unwrap().name.clone()));
-                }
-            } else {
-                items.push(ListItem::new(&room[..]));
+        for room_id in room_list {
+            let room = &self.holder.rooms[&room_id[..]];
+            let mut style = Style::default();
+            if room.unread_msgs > 0 {
+                style = style.add_modifier(Modifier::BOLD);
+            }
+            let mut spans = vec![];
+
+            // Presence of the other user in direct chats.
+            if let Some(partner) = self.holder.dm_partner(room_id) {
+                let presence = self.holder.users.get(partner)
+                    .map(|user| user.presence)
+                    .unwrap_or_default();
+                let color = match presence {
+                    Presence::Online => Color::Green,
+                    Presence::Unavailable => Color::Yellow,
+                    Presence::Offline => Color::DarkGray,
+                };
+                spans.push(Span::styled("● ", Style::default().fg(color)));
+            }
+            spans.push(Span::styled(self.holder.room_name(room_id), style));
+
+            // Mentions are counted apart and in a different colour.
+            if room.highlight_msgs > 0 {
+                spans.push(Span::raw(" "));
+                spans.push(Span::styled(
+                    [" @", &room.highlight_msgs.to_string(), " "].join(""),
+                    Style::default()
+                        .fg(Color::White)
+                        .bg(Color::Red)
+                        .add_modifier(Modifier::BOLD)));
             }
+            let other_msgs = room.unread_msgs.saturating_sub(
+                room.highlight_msgs);
+            if other_msgs > 0 {
+                spans.push(Span::raw(" "));
+                spans.push(Span::styled(
+                    [" ", &other_msgs.to_string(), " "].join(""),
+                    Style::default()
+                        .fg(self.config.colors.status_fg)
+                        .bg(self.config.colors.status_bg)));
+            }
+            items.push(ListItem::new(Spans::from(spans)));
         }
 
         // Color for window, if selected it varies.
-        let mut window_color = Color::White;
+        let mut window_color = self.config.colors.border;
         if window_i as usize == self.selected_window {
-            window_color = Color::Red;
+            window_color = self.config.colors.selected_border;
         }
 
         let items = List::new(items).block(Block::default()
-            .title("Room list")
-            .title_style(Style::default()
-                .fg(Color::Black))
+            .title(Span::styled("Room list", Style::default()
+                .fg(Color::Black)))
             .borders(window_borders)
             .border_style(Style::default()
                 .fg(Color::Black)
                 .bg(window_color)))
             .style(Style::default()
-                .fg(Color::White))
+                .fg(self.config.colors.text))
             .highlight_style(Style::default()
                 .fg(Color::Black)
                 .bg(Color::White))
@@ -247,21 +985,105 @@ impl App {
         } else {
             state.select(Some(self.windows[window_i as usize].selected_room));
         }
-        
+
+        let height = frame.size().height - match self.mode {
+            AppMode::Normal => { 1 },
+            _ => { 2 },
+        };
+        let invites_height = if self.holder.room_invites.is_empty() {
+            0
+        } else {
+            self.invites_height.min(height/2)
+        };
+
         frame.render_stateful_widget(items, tui::layout::Rect {
                 x: window_x,
                 y: 0,
                 width: window_w,
-                height: frame.size().height - match self.mode {
-                        AppMode::Insert => { 2 },
-                        _ => { 1 },
-                    },
+                height: height - invites_height,
             }, &mut state);
+
+        if invites_height > 0 {
+            self._render_invites(frame, window_i, tui::layout::Rect {
+                x: window_x,
+                y: height - invites_height,
+                width: window_w,
+                height: invites_height,
+            }, window_borders);
+        }
+    }
+
+    /// Renders the rooms the user was invited to, below the room list.
+    fn _render_invites<B: Backend>(&self, frame: &mut Frame<'_, B>,
+        window_i: i32, area: Rect, window_borders: Borders) {
+
+        let dim = Style::default().add_modifier(Modifier::DIM);
+        let mut items: Vec<ListItem> = vec![];
+        for room_id in &self.holder.room_invites {
+            let mut lines = vec![Spans::from(Span::styled(
+                self.holder.room_name(room_id),
+                Style::default().add_modifier(Modifier::BOLD)))];
+
+            if let Some(room) = self.holder.invited_rooms.get(room_id) {
+                let mut info = vec![];
+                if !room.inviter.is_empty() {
+                    info.push(["invited by", &self.holder.user_name(
+                        &room.inviter)].join(" "));
+                }
+                if !room.members.is_empty() {
+                    info.push([room.members.len().to_string(), "members"
+                        .to_string()].join(" "));
+                }
+                if !info.is_empty() {
+                    lines.push(Spans::from(Span::styled(info.join(", "),
+                        dim)));
+                }
+                if !room.topic.is_empty() {
+                    lines.push(Spans::from(Span::styled(room.topic.clone(),
+                        dim)));
+                }
+            }
+            items.push(ListItem::new(Text::from(lines)));
+        }
+
+        let focused = window_i as usize == self.selected_window
+            && self.invites_focused;
+        let window_color = if focused {
+            self.config.colors.selected_border
+        } else {
+            self.config.colors.border
+        };
+
+        let title = [
+            "Invites (",
+            &self.config.keys.accept.to_string(),
+            " accept, ",
+            &self.config.keys.reject.to_string(),
+            " reject)",
+        ].join("");
+        let items = List::new(items).block(Block::default()
+            .title(Span::styled(title, Style::default()
+                .fg(Color::Black)))
+            .borders(window_borders | Borders::TOP)
+            .border_style(Style::default()
+                .fg(Color::Black)
+                .bg(window_color)))
+            .style(Style::default()
+                .fg(self.config.colors.text))
+            .highlight_style(Style::default()
+                .fg(Color::Black)
+                .bg(Color::White))
+            .highlight_symbol("");
+
+        // The selected invite is the one answered even when not focused.
+        let mut state = ListState::default();
+        state.select(Some(self.selected_invite));
+        frame.render_stateful_widget(items, area, &mut state);
     }
 
     /// Render the "messages" windows for the app.
     fn _render_messages<B: Backend>(&self, frame: &mut Frame<'_, B>,
-        room_list: &Vec<&String>) {
+        room_list: &[&String]) {
 
         for (window_i, window) in self.windows.iter().enumerate() {
             /*
@@ -277,8 +1099,8 @@ impl App {
             let mut window_w = ((frame.size().width as usize)/
                 self.windows.len()) as u16;
             let mut window_x = window_w*(window_i as u16);
-            let round_factor = (frame.size().width - window_w*(
-                    self.windows.len() as u16)) as u16;
+            let round_factor = frame.size().width - window_w*(
+                    self.windows.len() as u16);
             if window_i == 0 {
                 window_w += round_factor;
             } else {
@@ -286,9 +1108,9 @@ impl App {
             }
             // Selected window draws on top of the others.
             if self.selected_window > 0 {
-                if window_i as usize == self.selected_window - 1 {
+                if window_i == self.selected_window - 1 {
                     window_w -= 1;
-                } else if window_i as usize == self.selected_window {
+                } else if window_i == self.selected_window {
                     window_x -= 1;
                     window_w += 1;
                 }
@@ -298,87 +1120,288 @@ impl App {
             let window_borders = {
                 let mut borders = Borders::TOP;
                 
-                if window_i != 0 && window_i as usize == self.selected_window {
+                if window_i != 0 && window_i == self.selected_window {
                     borders |= Borders::LEFT;   
                 }
 
-                if window_i as usize != self.windows.len() - 1 && window_i
-                    as usize + 1 != self.selected_window {
+                if window_i != self.windows.len() - 1
+                    && window_i + 1 != self.selected_window {
                     
                     borders |= Borders::RIGHT;
                 }
                 borders
             };
 
-            if window.selected_room_id == "" {
-                self._render_room_list(frame, room_list, window_i as i32,
-                    window_x, window_w, window_borders);
-                continue;
-            }
+            // Rooms the user left can't be shown anymore.
+            let room_data = match self.holder.rooms
+                .get(&window.selected_room_id) {
+                Some(room_data) => room_data,
+                None => {
+                    self._render_room_list(frame, room_list, window_i as i32,
+                        window_x, window_w, window_borders);
+                    continue;
+                }
+            };
 
             // Count the ammount of new lines.
             let newline_count: Vec<&str> = window.written_msg.matches("\n")
                 .collect();
             let newline_count = newline_count.len();
-            if newline_count > std::u16::MAX as usize {
+            if newline_count > u16::MAX as usize {
                 panic!("Somehow you typed so many new lines in your message the
                     computer could not count them, congrats I guess.");
             }
             let newline_count = newline_count as u16;
 
-            // Genrate the message and sender list.
-            let mut msg_list: Vec<ListItem> = vec![];
+            let lowbar = match self.mode {
+                AppMode::Normal => { 0 },
+                _ => { 1 },
+            };
+
+            let lowbar_height = match self.mode {
+                AppMode::Normal => { 3 },
+                _ => { 4 },
+            };
+
+            let list_height = frame.size().height.saturating_sub(
+                newline_count + lowbar + lowbar_height) as usize;
+
+            // Other users typing, shown over the input bar.
+            let mut typing: Vec<String> = room_data.typing.iter()
+                .filter(|user_id| **user_id != self.holder.user_id)
+                .map(|user_id| self.holder.user_name(user_id))
+                .collect();
+            typing.sort();
+            let typing = match typing.len() {
+                0 => String::new(),
+                1 => [&typing[0], " is typing…"].join(""),
+                n if n <= 3 => [join_names(&typing, 0), " are typing…"
+                    .to_string()].join(""),
+                n => [join_names(&typing[..2], n as u64 - 2),
+                    " are typing…".to_string()].join(""),
+            };
+            let typing_y = frame.size().height.saturating_sub(
+                lowbar + newline_count + 4);
+            let list_height = if typing.is_empty() {
+                list_height
+            } else {
+                list_height.min(typing_y.saturating_sub(1) as usize)
+            };
+
+            let selected = window_i == self.selected_window;
+            let move_x = if selected && window_i != 0 { 1 } else { 0 };
+            let move_w = if selected && window_i != self.windows.len() {
+                2
+            } else {
+                0
+            };
+            // Messages are wrapped to fit in their column.
+            let msg_w = (window_w - window_w/5 - move_w) as usize;
+
+            // Genrate the message and sender list, one item for each line.
+            // Mentions are coloured in the text of the messages on screen.
+            let mut msg_list: Vec<(Spans<'static>, bool)> = vec![];
             let mut sender_list: Vec<ListItem> = vec![];
-            let room_data = &self.holder.rooms.get(&window.selected_room_id);
-            for msg in &room_data.unwrap().messages {
-                msg_list.push(ListItem::new(&msg.content[..]));
+            // Lines of the selected message, counted from the bottom.
+            let mut selected_lines = None;
+            let mut new_messages = false;
+            let mut last_day = None;
+            for msg in &room_data.messages {
+                let hidden = matches!(msg.content, MessageContent::State(_))
+                    && !self.config.show_state_events;
+                let read_marker = msg.event_id == window.read_marker;
+                if hidden {
+                    new_messages |= read_marker;
+                    continue;
+                }
+                let dim = Style::default().add_modifier(Modifier::DIM);
 
-                let alias = self.holder.users.get(&msg.sender);
-                if alias.is_none() {
+                // Separator before the first message of each day.
+                let time = message_time(msg.timestamp, self.config.utc);
+                if let Some(separator) = time.and_then(|time| day_separator(
+                    &time, &mut last_day, &self.config.day_format)) {
+                    msg_list.push((Spans::from(Span::styled(separator, dim)),
+                        false));
+                    sender_list.push(ListItem::new(""));
+                }
+
+                // Divider before the first message after the read marker.
+                if new_messages {
+                    msg_list.push((Spans::from(Span::styled(
+                        "── new messages ──",
+                        Style::default().fg(Color::Red))), false));
                     sender_list.push(ListItem::new(""));
-                    continue;
                 }
-                let alias = alias.unwrap();
-                sender_list.push(ListItem::new(&alias.name[..]));
-                let newline_count: Vec<&str> = msg.content.matches("\n")
+                new_messages = read_marker;
+
+                let alias = match self.holder.users.get(&msg.sender) {
+                    Some(alias) => &alias.name[..],
+                    None => "",
+                };
+
+                // Other users who read up to this message.
+                let mut readers: Vec<String> = room_data.receipts.iter()
+                    .filter(|(user_id, event_id)| **event_id == msg.event_id
+                        && **user_id != self.holder.user_id)
+                    .map(|(user_id, _)| self.holder.user_name(user_id))
                     .collect();
-                for _ in newline_count {
-                    sender_list.push(ListItem::new(&alias.name[..]));
+                readers.sort();
+
+                let selected = !msg.event_id.is_empty()
+                    && msg.event_id == window.selected_msg;
+                let first_line = msg_list.len();
+
+                // Start of the message replied to.
+                if !msg.reply_to.is_empty() {
+                    let quote = match room_data.messages.iter()
+                        .find(|reply| reply.event_id == msg.reply_to) {
+                        Some(reply) => {
+                            let body = reply.content.body();
+                            [
+                                "↳ ",
+                                &self.holder.user_name(&reply.sender),
+                                ": ",
+                                body.lines().next().unwrap_or(""),
+                            ].join("")
+                        }
+                        None => String::from("↳ reply to an older message"),
+                    };
+                    msg_list.push((Spans::from(Span::styled(quote, dim)),
+                        false));
+                    sender_list.push(ListItem::new(""));
+                }
+
+                // Senders not known yet are named by their ID in the text.
+                let sender = self.holder.user_name(&msg.sender);
+                let (text, style) = message_text(&msg.content, &sender,
+                    &self.holder);
+                let style = style.fg(self.config.colors.text);
+                let lines = formatted_lines(msg, &sender, style,
+                    self.config.colors.code)
+                    .unwrap_or_else(|| text.split('\n')
+                        .map(|line| Spans::from(Span::styled(line.to_string(),
+                            style)))
+                        .collect());
+                // The time and sender are only shown next to the first line.
+                let line_count = lines.len();
+                let sender_line = msg_list.len();
+                let time = time.map(|time| time
+                    .format(&self.config.time_format).to_string());
+                let nick = Style::default()
+                    .fg(self.config.colors.nick_color(&msg.sender));
+                for (i, mut spans) in lines.into_iter().enumerate() {
+                    if i == line_count - 1 && msg.edited {
+                        spans.0.push(Span::styled(" (edited)", dim));
+                    }
+                    if i == line_count - 1 && !readers.is_empty() {
+                        spans.0.push(Span::styled(
+                            ["  ✓ ", &readers.join(", ")].join(""), dim));
+                    }
+                    for line in wrap(spans, msg_w) {
+                        let sender = if msg_list.len() != sender_line {
+                            Spans::default()
+                        } else if let Some(time) = &time {
+                            Spans::from(vec![
+                                Span::styled([time, " "].join(""), dim),
+                                Span::styled(alias, nick),
+                            ])
+                        } else {
+                            Spans::from(Span::styled(alias, nick))
+                        };
+                        sender_list.push(ListItem::new(sender));
+                        msg_list.push((line, true));
+                    }
+                }
+
+                // Reactions, the ones by the user in bold.
+                if !msg.reactions.is_empty() {
+                    let mut spans = Vec::new();
+                    for (key, count) in msg.reaction_counts() {
+                        let own = msg.reactions.iter().any(|r| r.key == key
+                            && r.sender == self.holder.user_id);
+                        let style = if own {
+                            Style::default().add_modifier(Modifier::BOLD)
+                        } else {
+                            Style::default()
+                        };
+                        spans.push(Span::styled(
+                            [key, " ", &count.to_string()].join(""), style));
+                        spans.push(Span::raw("  "));
+                    }
+                    for line in wrap(Spans::from(spans), msg_w) {
+                        msg_list.push((line, false));
+                        sender_list.push(ListItem::new(""));
+                    }
+                }
+
+                if selected {
+                    selected_lines = Some((first_line, msg_list.len()));
                 }
             }
 
+            // Scroll to show the selected message if it is not on screen.
+            if let Some((first, end)) = selected_lines {
+                let from_bottom = msg_list.len() - end;
+                let top_scroll = (msg_list.len() - first)
+                    .saturating_sub(list_height);
+                let scroll = window.scroll.get();
+                if scroll > from_bottom {
+                    window.scroll.set(from_bottom);
+                } else if scroll < top_scroll {
+                    window.scroll.set(top_scroll);
+                }
+            }
+
+            // Only keep the lines that fit, scrolled from the bottom.
+            let max_scroll = msg_list.len().saturating_sub(list_height);
+            window.max_scroll.set(max_scroll);
+            let scroll = window.scroll.get().min(max_scroll);
+            let end = msg_list.len() - scroll;
+            let start = end.saturating_sub(list_height);
+            sender_list.truncate(end);
+            sender_list.drain(..start);
+            let reversed = Style::default().add_modifier(Modifier::REVERSED);
+            let msg_list: Vec<ListItem> = msg_list.into_iter().enumerate()
+                .take(end)
+                .skip(start)
+                .map(|(i, (line, text))| {
+                    let line = if text {
+                        color_mentions(line, &room_data.mention_names,
+                            &self.config.colors)
+                    } else {
+                        line
+                    };
+                    match selected_lines {
+                        Some((first, end)) if (first..end).contains(&i) => {
+                            ListItem::new(line).style(reversed)
+                        }
+                        _ => ListItem::new(line),
+                    }
+                })
+                .collect();
+
             // List for rust-tui to render.
-            let msg_borders = Borders::RIGHT;
             let msg_items = List::new(msg_list).block(Block::default())
                 .style(Style::default()
-                    .fg(Color::White));
+                    .fg(self.config.colors.text));
 
             // List for rust-tui to render.
             let sender_items = List::new(sender_list).block(Block::default()
-                .title_style(Style::default()
-                    .fg(Color::Black)
-                    .bg(Color::White))
                 .borders(Borders::RIGHT))
                 .style(Style::default()
-                    .fg(Color::White));
+                    .fg(self.config.colors.text));
 
             // Temp variable so rustc is happy.
             let mut state = ListState::default();
 
             // Color for window, if selected it varies.
-            let mut window_color = Color::White;
+            let mut window_color = self.config.colors.border;
             if window_i == self.selected_window {
-                window_color = Color::Red;
+                window_color = self.config.colors.selected_border;
             }
 
-            let lowbar = match self.mode {
-                AppMode::Normal => { 0 },
-                _ => { 1 },
-            };
-
             // Draw main box for title.
-            let room_title = &window.selected_room_id[..];
-            let room_title = ["Messages for room ", room_title].join(" ");
+            let room_title = self.holder.room_name(&window.selected_room_id);
             frame.render_widget(Paragraph::new("").block(Block::default()
                     .title(room_title)
                     .borders(window_borders)
@@ -386,7 +1409,7 @@ impl App {
                         .fg(Color::Black)
                         .bg(window_color)))
                     .style(Style::default()
-                       .fg(Color::White)
+                       .fg(self.config.colors.text)
                        .bg(Color::Black))
                     .alignment(Alignment::Left),
                 tui::layout::Rect {
@@ -400,19 +1423,6 @@ impl App {
                 },
                 );
 
-            let lowbar_height = match self.mode {
-                AppMode::Normal => { 3 },
-                _ => { 4 },
-            };
-
-            let selected = window_i as usize == self.selected_window;
-            let move_x = if selected && window_i != 0 { 1 } else { 0 };
-            let move_w = if selected && window_i as usize != self.windows.len(){
-                2
-            } else {
-                0
-            };
-
             // Draw senders for room.
             frame.render_stateful_widget(sender_items, tui::layout::Rect {
                     x: window_x + move_x,
@@ -431,22 +1441,48 @@ impl App {
                         lowbar_height,
                 }, &mut state);
 
+            if !typing.is_empty() {
+                frame.render_widget(
+                    Paragraph::new(typing)
+                        .style(Style::default()
+                            .fg(self.config.colors.text)
+                            .add_modifier(Modifier::ITALIC)),
+                    tui::layout::Rect {
+                        x: window_x + move_x,
+                        y: typing_y,
+                        width: window_w - move_w,
+                        height: 1,
+                    });
+            }
+
             /* 
              * Draw input bar if necessary. This bar is only drawn if INSERT
              * mode is active or if text was previously written to it.
              */
             let in_insert_mode = matches!(&self.mode, AppMode::Insert);
             let is_selected = window_i == self.selected_window;
-            if window.written_msg != "" || (in_insert_mode && is_selected) {
+            if !window.written_msg.is_empty()
+                || (in_insert_mode && is_selected) {
+                let title = if !window.editing.is_empty() {
+                    String::from(" Editing message ")
+                } else if let Some(msg) = room_data.messages.iter()
+                    .find(|msg| !window.reply_to.is_empty()
+                        && msg.event_id == window.reply_to) {
+                    [" Replying to ", &self.holder.user_name(&msg.sender), " "]
+                        .join("")
+                } else {
+                    String::new()
+                };
                 frame.render_widget(
                     Paragraph::new([" $> ", &window.written_msg[..]].join(""))
                         .block(Block::default()
+                            .title(title)
                             .borders(Borders::TOP)
                             .border_style(Style::default()
                                 .fg(Color::Black)
                                 .bg(Color::White))
                             .style(Style::default()
-                                .fg(Color::White)
+                                .fg(self.config.colors.text)
                                 .bg(Color::Black)))
                             .alignment(Alignment::Left),
                     tui::layout::Rect {
@@ -458,21 +1494,176 @@ impl App {
         }
     }
 
-    /// Sends the written message to the currently selected room.
-    pub fn send_message(&mut self) -> json::JsonValue {
-        let post_data = json::object! {
-            "msgtype": "m.text",
-            "body": &self.windows[self.selected_window].written_msg[..],
+    /// Sends the written message to the currently selected room. The message
+    /// is kept in the input bar if it could not be sent.
+    pub fn send_message(&mut self) {
+        let window = &self.windows[self.selected_window];
+        let text = window.written_msg.clone();
+        let room = window.selected_room_id.clone();
+
+        // Edits keep the type of the message they replace.
+        let msgtype = self.holder.rooms.get(&room)
+            .and_then(|r| r.messages.iter()
+                .find(|msg| msg.event_id == window.editing))
+            .map(|msg| match msg.content {
+                MessageContent::Emote(_) => "m.emote",
+                MessageContent::Notice(_) => "m.notice",
+                _ => "m.text",
+            })
+            .unwrap_or("m.text");
+
+        let res = match text.strip_prefix('/') {
+            // A double slash sends the message without the first one.
+            Some(rest) if !rest.starts_with('/') => {
+                run_slash_command(self, rest)
+            }
+            Some(rest) => send_text(self, &room, msgtype, rest),
+            None => send_text(self, &room, msgtype, &text),
         };
 
-        self.windows[self.selected_window].written_msg = String::new();
-        self.windows[self.selected_window].selected_char = 1;
+        // The text is kept so it can be fixed and sent again.
+        match res {
+            Ok(()) => {
+                let window = &mut self.windows[self.selected_window];
+                window.written_msg = String::new();
+                window.selected_char = 1;
+            }
+            Err(e) => {
+                self.error = e;
+            }
+        }
+    }
+}
+
+/// Lines of a formatted message, or `None` if it is not formatted or its HTML
+/// cannot be shown.
+fn formatted_lines(msg: &Message, sender: &str, style: Style, code: Color)
+    -> Option<Vec<Spans<'static>>> {
+    if msg.html.is_empty() {
+        return None;
+    }
+    match &msg.content {
+        MessageContent::Text(_) | MessageContent::Notice(_) => {
+            html::to_lines(&msg.html, style, code)
+        }
+        MessageContent::Emote(_) => {
+            let mut lines = html::to_lines(&msg.html, style, code)?;
+            lines[0].0.insert(0, Span::styled(["* ", sender, " "].join(""),
+                style));
+            Some(lines)
+        }
+        _ => None,
+    }
+}
+
+/// Text shown for a message and the style to show it with. `sender` is the
+/// name of the user who sent it.
+fn message_text(content: &MessageContent, sender: &str, holder: &DataHolder)
+    -> (String, Style) {
+    let style = Style::default();
+    match content {
+        MessageContent::Text(body) | MessageContent::Other(body) => {
+            (body.clone(), style)
+        }
+        MessageContent::Notice(body) => {
+            (body.clone(), style.add_modifier(Modifier::DIM))
+        }
+        MessageContent::Emote(body) => {
+            let text = ["*", sender, body].join(" ");
+            (text, style.add_modifier(Modifier::ITALIC))
+        }
+        MessageContent::File { kind, name, size, url } => {
+            let kind = match kind {
+                FileKind::Image => "[image]",
+                FileKind::File => "[file]",
+                FileKind::Audio => "[audio]",
+                FileKind::Video => "[video]",
+            };
+            let size = match size {
+                Some(size) => ["(", &format_size(*size), ")"].join(""),
+                None => String::new(),
+            };
+            let text = [kind, name, &size, url].iter()
+                .filter(|part| !part.is_empty())
+                .copied()
+                .collect::<Vec<&str>>()
+                .join(" ");
+            (text, style.add_modifier(Modifier::UNDERLINED))
+        }
+        MessageContent::Location { body, geo_uri } => {
+            (["[location]", body, geo_uri].join(" "), style)
+        }
+        MessageContent::State(change) => {
+            (state_text(change, sender, holder),
+                style.add_modifier(Modifier::DIM))
+        }
+        MessageContent::Redacted => {
+            (String::from("message deleted"),
+                style.add_modifier(Modifier::DIM | Modifier::ITALIC))
+        }
+    }
+}
+
+/// Describes a change in the room state, done by `sender`.
+fn state_text(change: &StateChange, sender: &str, holder: &DataHolder)
+    -> String {
+    let name = |user_id: &str| holder.user_name(user_id);
+
+    match change {
+        StateChange::Joined => [sender, "joined"].join(" "),
+        StateChange::Left => [sender, "left"].join(" "),
+        StateChange::Invited(user) => [sender, "invited", &name(user)]
+            .join(" "),
+        StateChange::Kicked(user) => [sender, "kicked", &name(user)]
+            .join(" "),
+        StateChange::Banned(user) => [sender, "banned", &name(user)]
+            .join(" "),
+        StateChange::Unbanned(user) => [sender, "unbanned", &name(user)]
+            .join(" "),
+        StateChange::DisplayName { old, new } if new.is_empty() => {
+            [old, "removed their display name"].join(" ")
+        }
+        StateChange::DisplayName { old, new } => {
+            [old, "changed their name to", new].join(" ")
+        }
+        StateChange::UserAvatar => [sender, "changed their avatar"].join(" "),
+        StateChange::Name(room_name) if room_name.is_empty() => {
+            [sender, "removed the room name"].join(" ")
+        }
+        StateChange::Name(room_name) => {
+            [sender, "changed the room name to", room_name].join(" ")
+        }
+        StateChange::Topic(topic) if topic.is_empty() => {
+            [sender, "removed the topic"].join(" ")
+        }
+        StateChange::Topic(topic) => {
+            [sender, "changed the topic to", topic].join(" ")
+        }
+        StateChange::RoomAvatar => {
+            [sender, "changed the room avatar"].join(" ")
+        }
+        StateChange::PowerLevels => {
+            [sender, "changed the power levels"].join(" ")
+        }
+        StateChange::Encryption(algorithm) => {
+            [sender, " enabled encryption (", algorithm, ")"].join("")
+        }
+    }
+}
+
+/// Formats a size in bytes for humans.
+fn format_size(size: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
 
-        let room = self.windows[self.selected_window].selected_room_id.clone();
-        self.holder.server.post_data_token(
-            &["rooms", &room[..], "send/m.room.message"]
-                .join("/")[..],
-            &post_data.to_string()[..],
-            &self.holder.token[..])
+    if unit == 0 {
+        format!("{} {}", size, units[0])
+    } else {
+        format!("{:.1} {}", value, units[unit])
     }
 }
@@ -18,8 +18,13 @@
 
 use std:: {
     str,
+    cmp::Ordering,
     collections::HashMap,
+    error,
+    fmt,
     io::Read,
+    sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use json::JsonValue;
 use curl::easy::Easy;
@@ -29,80 +34,613 @@ pub struct DataHolder {
     pub server: Server,
     pub user_id: String,
     pub token: String,
+    pub device_id: String,
+    /// ID of the uploaded sync filter, empty if there is none yet.
+    pub filter_id: String,
 
     pub rooms: HashMap<String, RoomData>,
     pub users: HashMap<String, UserData>,
     pub room_invites: Vec<String>,
+    /// Preview of the rooms in `room_invites`, from their invite state.
+    pub invited_rooms: HashMap<String, RoomData>,
+    /// Global account data events, by type.
+    pub account_data: HashMap<String, JsonValue>,
 
     pub next_batch: String,
 }
 
+impl DataHolder {
+    /// Creates an empty holder for the given server.
+    pub fn new(server: Server) -> Self {
+        Self {
+            server,
+            user_id: String::new(),
+            token: String::new(),
+            device_id: String::new(),
+            filter_id: String::new(),
+            rooms: HashMap::new(),
+            users: HashMap::new(),
+            room_invites: vec![],
+            invited_rooms: HashMap::new(),
+            account_data: HashMap::new(),
+            next_batch: String::new(),
+        }
+    }
+
+    /// Display name of a user, or their ID if it is not known.
+    pub fn user_name(&self, user_id: &str) -> String {
+        match self.users.get(user_id) {
+            Some(user) if !user.name.is_empty() => user.name.clone(),
+            _ => user_id.to_string(),
+        }
+    }
+
+    /// User the room is a direct chat with, from the `m.direct` account
+    /// data.
+    pub fn dm_partner(&self, room_id: &str) -> Option<&str> {
+        let direct = self.account_data.get("m.direct")?;
+        direct.entries()
+            .find(|(_, rooms)| rooms.members().any(|id| *id == room_id))
+            .map(|(user_id, _)| user_id)
+    }
+
+    /// Rooms in the order they are listed: favourites first and low priority
+    /// ones last, each group with the most recently active rooms on top.
+    pub fn sorted_rooms(&self) -> Vec<&String> {
+        let mut rooms: Vec<(&String, &RoomData)> = self.rooms.iter().collect();
+        rooms.sort_by(|(a_id, a), (b_id, b)| {
+            a.list_group().cmp(&b.list_group())
+                .then(a.tag_order().partial_cmp(&b.tag_order())
+                    .unwrap_or(Ordering::Equal))
+                .then(b.last_activity.cmp(&a.last_activity))
+                .then(a_id.cmp(b_id))
+        });
+        rooms.into_iter().map(|(room_id, _)| room_id).collect()
+    }
+
+    /// Name shown for a room, following the algorithm in the Matrix spec: the
+    /// room name, its canonical alias or the names of some of its members.
+    pub fn room_name(&self, room_id: &str) -> String {
+        let room = match self.rooms.get(room_id)
+            .or_else(|| self.invited_rooms.get(room_id)) {
+            Some(room) => room,
+            None => return room_id.to_string(),
+        };
+        if !room.name.is_empty() {
+            return room.name.clone();
+        }
+        if !room.alias.is_empty() {
+            return room.alias.clone();
+        }
+
+        // Without a summary the known members are used instead.
+        let mut heroes: Vec<&String> = room.heroes.iter().collect();
+        if heroes.is_empty() {
+            heroes = room.members.iter()
+                .filter(|id| **id != self.user_id)
+                .collect();
+            heroes.sort();
+            heroes.truncate(5);
+        }
+        let names: Vec<String> = heroes.iter()
+            .map(|id| self.user_name(id))
+            .collect();
+
+        let mut total = room.joined_count + room.invited_count;
+        if total == 0 {
+            total = room.members.len() as u64;
+        }
+
+        if names.is_empty() {
+            String::from("Empty room")
+        } else if total <= 1 {
+            ["Empty room (was ", &join_names(&names, 0), ")"].join("")
+        } else {
+            let others = (total - 1).saturating_sub(names.len() as u64);
+            join_names(&names, others)
+        }
+    }
+}
+
+impl RoomData {
+    /// Group of the room in the room list: favourites, the rest and low
+    /// priority rooms.
+    fn list_group(&self) -> u8 {
+        if self.tag("m.favourite").is_some() {
+            0
+        } else if self.tag("m.lowpriority").is_some() {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Order of the room inside its tag, rooms without one go last.
+    fn tag_order(&self) -> f64 {
+        let tag = match self.list_group() {
+            0 => "m.favourite",
+            2 => "m.lowpriority",
+            _ => return 0.0,
+        };
+        self.tag(tag).and_then(|tag| tag["order"].as_f64()).unwrap_or(2.0)
+    }
+
+    fn tag(&self, tag: &str) -> Option<&JsonValue> {
+        let tag = &self.account_data.get("m.tag")?["tags"][tag];
+        if tag.is_null() {
+            None
+        } else {
+            Some(tag)
+        }
+    }
+}
+
+/// Joins user names as "a, b and c", or "a, b and 3 others".
+pub fn join_names(names: &[String], others: u64) -> String {
+    if others > 0 {
+        return [names.join(", "), format!("{} others", others)]
+            .join(" and ");
+    }
+    match names.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => [rest.join(", "), last.clone()].join(" and "),
+        None => String::new(),
+    }
+}
+
 pub struct Message {
+    pub event_id: String,
     pub sender: String,
     pub room: String,
-    pub content: String,
-    pub msgtype: String
+    pub content: MessageContent,
+    /// Time the message was sent, in milliseconds since the epoch.
+    pub timestamp: u64,
+    /// HTML version of the text, empty if the message is not formatted.
+    pub html: String,
+    /// Event this message is a reply to, if any.
+    pub reply_to: String,
+    /// The content was replaced by an edit.
+    pub edited: bool,
+    /// Reactions to the message, in the order they were received.
+    pub reactions: Vec<Reaction>,
+}
+
+impl Message {
+    /// Reaction keys with the number of users who used them, in the order
+    /// they were first used.
+    pub fn reaction_counts(&self) -> Vec<(&str, usize)> {
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for reaction in &self.reactions {
+            match counts.iter_mut().find(|(key, _)| *key == reaction.key) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((&reaction.key, 1)),
+            }
+        }
+        counts
+    }
+}
+
+/// An `m.annotation` of a message by a user.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reaction {
+    pub event_id: String,
+    pub sender: String,
+    /// Usually an emoji.
+    pub key: String,
+}
+
+/// Kinds of files that can be sent in a message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileKind {
+    Image,
+    File,
+    Audio,
+    Video,
+}
+
+/// Content of a message, depending on its `msgtype`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MessageContent {
+    Text(String),
+    Notice(String),
+    /// Action done by the sender, like IRC's `/me`.
+    Emote(String),
+    File {
+        kind: FileKind,
+        name: String,
+        /// Size in bytes, if known.
+        size: Option<u64>,
+        /// `mxc://` URL of the file.
+        url: String,
+    },
+    Location {
+        body: String,
+        geo_uri: String,
+    },
+    /// Unknown message type, only its fallback body can be shown.
+    Other(String),
+    /// Change in the room state, not sent as a message.
+    State(StateChange),
+    /// The message was deleted.
+    Redacted,
 }
 
+/// Change in the state of a room, shown in the timeline. The users are the
+/// targets of the change, the user who did it is the sender of the event.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateChange {
+    Joined,
+    Left,
+    Invited(String),
+    Kicked(String),
+    Banned(String),
+    Unbanned(String),
+    /// Display name of the sender changed, the old one is kept as the user
+    /// data already has the new one.
+    DisplayName { old: String, new: String },
+    UserAvatar,
+    Name(String),
+    Topic(String),
+    RoomAvatar,
+    PowerLevels,
+    /// Encryption was enabled with the given algorithm.
+    Encryption(String),
+}
+
+impl StateChange {
+    /// Parses a state event, if it is one of the shown ones.
+    pub fn parse(event: &JsonValue) -> Option<Self> {
+        let content = &event["content"];
+        let text = |key: &str| content[key].as_str().unwrap_or("")
+            .to_string();
+
+        let change = match event["type"].as_str()? {
+            "m.room.member" => {
+                let target = event["state_key"].as_str()?.to_string();
+                let own = event["sender"] == target[..];
+                let prev = if event["unsigned"]["prev_content"].is_object() {
+                    &event["unsigned"]["prev_content"]
+                } else {
+                    &event["prev_content"]
+                };
+                let prev_membership = prev["membership"].as_str()
+                    .unwrap_or("leave");
+
+                match (prev_membership, content["membership"].as_str()?) {
+                    ("join", "join") => {
+                        if prev["displayname"] != content["displayname"] {
+                            StateChange::DisplayName {
+                                old: prev["displayname"].as_str()
+                                    .unwrap_or(&target).to_string(),
+                                new: text("displayname"),
+                            }
+                        } else if prev["avatar_url"] != content["avatar_url"] {
+                            StateChange::UserAvatar
+                        } else {
+                            return None;
+                        }
+                    }
+                    (_, "join") => StateChange::Joined,
+                    (_, "invite") => StateChange::Invited(target),
+                    (_, "ban") => StateChange::Banned(target),
+                    ("ban", "leave") => StateChange::Unbanned(target),
+                    (_, "leave") if own => StateChange::Left,
+                    (_, "leave") => StateChange::Kicked(target),
+                    _ => return None,
+                }
+            }
+            "m.room.name" => StateChange::Name(text("name")),
+            "m.room.topic" => StateChange::Topic(text("topic")),
+            "m.room.avatar" => StateChange::RoomAvatar,
+            "m.room.power_levels" => StateChange::PowerLevels,
+            "m.room.encryption" => StateChange::Encryption(text("algorithm")),
+            _ => return None,
+        };
+        Some(change)
+    }
+}
+
+impl MessageContent {
+    /// Parses the content of an `m.room.message` event.
+    pub fn parse(content: &JsonValue) -> Self {
+        let body = content["body"].as_str().unwrap_or("").to_string();
+        let kind = match content["msgtype"].as_str().unwrap_or("") {
+            "m.text" => return MessageContent::Text(body),
+            "m.notice" => return MessageContent::Notice(body),
+            "m.emote" => return MessageContent::Emote(body),
+            "m.location" => return MessageContent::Location {
+                body,
+                geo_uri: content["geo_uri"].as_str().unwrap_or("")
+                    .to_string(),
+            },
+            "m.image" => FileKind::Image,
+            "m.file" => FileKind::File,
+            "m.audio" => FileKind::Audio,
+            "m.video" => FileKind::Video,
+            _ => return MessageContent::Other(body),
+        };
+
+        // Newer clients send the file name apart from the caption.
+        let name = content["filename"].as_str().unwrap_or(&body).to_string();
+        MessageContent::File {
+            kind,
+            name,
+            size: content["info"]["size"].as_u64(),
+            url: content["url"].as_str().unwrap_or("").to_string(),
+        }
+    }
+
+    /// HTML version of the text of an `m.room.message` event, or an empty
+    /// string if it has none.
+    pub fn formatted_body(content: &JsonValue) -> String {
+        match content["msgtype"].as_str() {
+            Some("m.text") | Some("m.notice") | Some("m.emote")
+                if content["format"] == "org.matrix.custom.html" => {
+                content["formatted_body"].as_str().unwrap_or("").to_string()
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Removes the quote of the replied message from a reply. It is only
+    /// there for clients that do not show replies.
+    pub fn strip_reply_fallback(self) -> Self {
+        let strip = |body: String| {
+            if !body.starts_with("> ") {
+                return body;
+            }
+            let mut lines = body.lines()
+                .skip_while(|line| line.starts_with('>'))
+                .peekable();
+            if lines.peek() == Some(&"") {
+                lines.next();
+            }
+            lines.collect::<Vec<&str>>().join("\n")
+        };
+
+        match self {
+            MessageContent::Text(body) => MessageContent::Text(strip(body)),
+            MessageContent::Notice(body) => MessageContent::Notice(strip(body)),
+            MessageContent::Emote(body) => MessageContent::Emote(strip(body)),
+            content => content,
+        }
+    }
+
+    /// Plain text version of the message.
+    pub fn body(&self) -> &str {
+        match self {
+            MessageContent::Text(body)
+                | MessageContent::Notice(body)
+                | MessageContent::Emote(body)
+                | MessageContent::Location { body, .. }
+                | MessageContent::Other(body) => body,
+            MessageContent::File { name, .. } => name,
+            MessageContent::State(_) | MessageContent::Redacted => "",
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct UserData {
     pub name: String,
-    pub is_online: bool,
     pub rooms: Vec<String>,
+
+    pub presence: Presence,
+    /// Milliseconds since the user was last active, when the presence was
+    /// received.
+    pub last_active_ago: Option<u64>,
+    pub status_msg: String,
 }
 
+/// Availability of a user.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Presence {
+    Online,
+    Unavailable,
+    #[default]
+    Offline,
+}
+
+impl Presence {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "online" => Some(Presence::Online),
+            "unavailable" => Some(Presence::Unavailable),
+            "offline" => Some(Presence::Offline),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Presence::Online => "online",
+            Presence::Unavailable => "unavailable",
+            Presence::Offline => "offline",
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct RoomData {
     pub alias: String,
+    pub name: String,
+    pub topic: String,
     pub members: Vec<String>,
+    /// Display names and IDs of the members with the ID they belong to,
+    /// longest first, to find where they are mentioned.
+    pub mention_names: Vec<(String, String)>,
     pub messages: Vec<Message>,
+    /// Edits and reactions of messages that are not loaded yet, applied
+    /// when they are.
+    pub pending_relations: Vec<JsonValue>,
     pub unread_msgs: u32,
+    /// Unread messages that mention the user.
+    pub highlight_msgs: u32,
+    /// Time of the newest event, in milliseconds since the epoch.
+    pub last_activity: u64,
+
+    /// Members used to name the room when it has no name, from the summary.
+    pub heroes: Vec<String>,
+    /// Number of joined members, from the summary.
+    pub joined_count: u64,
+    /// Number of invited members, from the summary.
+    pub invited_count: u64,
+    /// User who sent the invite, for rooms the user was invited to.
+    pub inviter: String,
+
+    /// Token to request the messages before the first one in `messages`.
+    pub prev_batch: String,
+    /// Users typing right now.
+    pub typing: Vec<String>,
+    /// Last event read by each user.
+    pub receipts: HashMap<String, String>,
+    /// Room account data events, by type.
+    pub account_data: HashMap<String, JsonValue>,
 }
 
+/// Errors returned by requests to the server.
+#[derive(Clone, Debug)]
+pub enum ClientError {
+    /// The request could not be sent or the answer could not be received.
+    Transport(String),
+    /// The server answered with an HTTP error without a Matrix error.
+    Http(u32),
+    /// The server answered with a Matrix error.
+    Matrix {
+        status: u32,
+        errcode: String,
+        error: String,
+    },
+    /// Too many requests were sent, wait before sending the next one.
+    RateLimited {
+        retry_after_ms: u64,
+    },
+    /// The answer is not valid JSON.
+    Decode(String),
+}
+
+impl ClientError {
+    /// Returns the Matrix error code, if the server sent one.
+    pub fn errcode(&self) -> Option<&str> {
+        match self {
+            ClientError::Matrix { errcode, .. } => Some(&errcode[..]),
+            ClientError::RateLimited { .. } => Some("M_LIMIT_EXCEEDED"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(e) => write!(f, "Connection error: {}", e),
+            ClientError::Http(status) => write!(f, "HTTP error {}", status),
+            ClientError::Matrix { errcode, error, .. } => {
+                write!(f, "{}: {}", errcode, error)
+            }
+            ClientError::RateLimited { retry_after_ms } => write!(f,
+                "Rate limited, retry in {} ms", retry_after_ms),
+            ClientError::Decode(e) => write!(f, "Invalid answer: {}", e),
+        }
+    }
+}
+
+impl error::Error for ClientError {}
+
+impl From<curl::Error> for ClientError {
+    fn from(e: curl::Error) -> Self {
+        if e.is_operation_timedout() {
+            return ClientError::Transport(String::from("the server took too \
+                long to answer"));
+        }
+        ClientError::Transport(e.to_string())
+    }
+}
+
+/// Result of a request to the server.
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// Time limit of a request to the server, so an unanswered request does not
+/// freeze the interface.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Time to connect to the server.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time a sync can take apart from the time the server waits for events.
+const SYNC_MARGIN: Duration = Duration::from_secs(30);
+
+/// Time limit of a full state sync, which can take long for big accounts.
+const FULL_SYNC_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
 pub struct Server {
     pub address: String,
 }
 
 impl Server {
     pub fn get_data_token(&self, url: &str, params: Vec<&str>, token: &str)
-        -> JsonValue {
-        
+        -> ClientResult<JsonValue> {
+        self.get_data_timeout(url, params, token, REQUEST_TIMEOUT)
+    }
+
+    /// Gets data from the server with a user token, waiting up to `timeout`
+    /// for the answer.
+    pub fn get_data_timeout(&self, url: &str, params: Vec<&str>, token: &str,
+        timeout: Duration) -> ClientResult<JsonValue> {
         let mut params_str = String::from("");
-        for (_, param) in params.iter().enumerate() {
-            params_str.push_str("&");
+        for param in params.iter() {
+            params_str.push('&');
             params_str.push_str(param);
         }
         let url = [&self.address[..], "/_matrix/client/r0/", url,
                    "?access_token=", token, &params_str[..]].join("");
-        self._perform_request(&url[..], "")
+        self._perform_request("GET", &url[..], "", timeout)
     }
 
     /// Posts data to the server with a user token.
     pub fn post_data_token(&self, url: &str, data: &str, token: &str)
-        -> JsonValue {
+        -> ClientResult<JsonValue> {
         let url = [url, "?access_token=", token].join("");
         self.post_data(&url[..], data)
     }
 
     /// Posts data to the server without including the user token.
-    pub fn post_data(&self, url: &str, data: &str) -> JsonValue {
+    pub fn post_data(&self, url: &str, data: &str) -> ClientResult<JsonValue> {
         let url = &str::replace(url, ":", "%3A")[..];
         let url = [&self.address[..], "/_matrix/client/r0/", url].join("");
-        self._perform_request(&url[..], data)
+        self._perform_request("POST", &url[..], data, REQUEST_TIMEOUT)
     }
 
-    /// Sends a request to the server.
-    fn _perform_request(&self, url: &str, data: &str) -> JsonValue {
-        let post_params = data.clone();
+    /// Puts data in the server with a user token.
+    pub fn put_data_token(&self, url: &str, data: &str, token: &str)
+        -> ClientResult<JsonValue> {
+        let url = &str::replace(url, ":", "%3A")[..];
+        let url = [&self.address[..], "/_matrix/client/r0/", url,
+                   "?access_token=", token].join("");
+        self._perform_request("PUT", &url[..], data, REQUEST_TIMEOUT)
+    }
+
+    /// Sends a request to the server. It fails if the whole request takes
+    /// longer than `timeout`.
+    fn _perform_request(&self, method: &str, url: &str, data: &str,
+        timeout: Duration) -> ClientResult<JsonValue> {
         let mut data = data.as_bytes();
         let mut return_data = Vec::new();
 
+        let mut handle = Easy::new();
         {
-            let mut handle = Easy::new();
-            handle.url(&url[..]).unwrap();
+            handle.url(url)?;
+            handle.connect_timeout(CONNECT_TIMEOUT)?;
+            handle.timeout(timeout)?;
 
             // No POST data, don't post!
-            if data.len() != 0 {
-                handle.post(true).unwrap();
-                handle.post_field_size(data.len() as u64).unwrap();
+            if !data.is_empty() {
+                handle.post(true)?;
+                handle.post_field_size(data.len() as u64)?;
+            }
+            if method != "GET" && method != "POST" {
+                handle.custom_request(method)?;
             }
 
             let mut transfer = handle.transfer();
@@ -111,130 +649,318 @@ impl Server {
              * FIXME This looks ugly, but I can't figure how to merge this with
              * the other "if" statement without rustc complaining.
              */
-            if data.len() != 0 {
+            if !data.is_empty() {
                 transfer.read_function(|buf| {
                     Ok(data.read(buf).unwrap_or(0))
-                }).unwrap();
+                })?;
             }
 
             transfer.write_function(|new_data| {
                 return_data.extend_from_slice(new_data);
                 Ok(new_data.len())
-            }).unwrap();
-            transfer.perform().unwrap();
+            })?;
+            transfer.perform()?;
         }
-
-        let return_data = match str::from_utf8(&return_data) {
-            Ok(v) => v,
-            Err(_) => panic!("Data can not be converted to aray!")
+        let status = handle.response_code()?;
+
+        let return_data = str::from_utf8(&return_data)
+            .map_err(|e| ClientError::Decode(e.to_string()))?;
+        let res = match json::parse(return_data) {
+            Ok(res) => res,
+            Err(_) if status >= 400 => return Err(ClientError::Http(status)),
+            Err(e) => return Err(ClientError::Decode(e.to_string())),
         };
 
-        json::parse(return_data).unwrap()
+        if res["errcode"] == "M_LIMIT_EXCEEDED" {
+            return Err(ClientError::RateLimited {
+                retry_after_ms: res["retry_after_ms"].as_u64().unwrap_or(5000),
+            });
+        }
+        if res["errcode"].is_string() {
+            return Err(ClientError::Matrix {
+                status,
+                errcode: res["errcode"].to_string(),
+                error: res["error"].to_string(),
+            });
+        }
+        if status >= 400 {
+            return Err(ClientError::Http(status));
+        }
+
+        Ok(res)
+    }
+}
+
+/// Event types shown in the room timelines, the rest are filtered out by the
+/// server.
+pub const TIMELINE_TYPES: &[&str] = &[
+    "m.room.message",
+    "m.room.member",
+    "m.room.name",
+    "m.room.canonical_alias",
+    "m.room.topic",
+    "m.room.avatar",
+    "m.room.power_levels",
+    "m.room.encryption",
+    "m.room.redaction",
+    "m.reaction",
+];
+
+/// Maximum number of timeline events the server sends for each room.
+pub const TIMELINE_LIMIT: u32 = 50;
+
+/// Filter used for every sync. Members are lazy loaded so the initial sync of
+/// big rooms does not include every member, and only the events determinant
+/// shows are sent.
+pub fn sync_filter() -> JsonValue {
+    json::object! {
+        "room": {
+            "state": {
+                "lazy_load_members": true
+            },
+            "timeline": {
+                "limit": TIMELINE_LIMIT,
+                "types": TIMELINE_TYPES
+            },
+            "ephemeral": {
+                "types": ["m.typing", "m.receipt"]
+            },
+            "account_data": {
+                "types": ["m.tag", "m.fully_read"]
+            }
+        },
+        "presence": {
+            "types": ["m.presence"]
+        }
     }
 }
 
-pub fn sync(holder: &mut DataHolder) -> json::JsonValue {
+/// Uploads the sync filter and returns its ID.
+pub fn create_filter(srv: &Server, token: &str, user_id: &str)
+    -> ClientResult<String> {
+    let res = srv.post_data_token(
+        &["user", user_id, "filter"].join("/")[..],
+        &sync_filter().dump()[..],
+        token)?;
+
+    match res["filter_id"].as_str() {
+        Some(filter_id) => Ok(filter_id.to_string()),
+        None => Err(ClientError::Decode(String::from("no filter ID"))),
+    }
+}
+
+/// Asks the server for the events since the `since` token. If there are none
+/// the server waits up to `timeout` milliseconds for new ones before
+/// answering. `full_state` makes the server send the whole state of the rooms
+/// instead of only the changes.
+pub fn sync_request(srv: &Server, token: &str, since: &str, filter: &str,
+    timeout: u64, full_state: bool, presence: Presence)
+    -> ClientResult<JsonValue> {
+    let presence = ["set_presence=", presence.as_str()].join("");
     let mut params = vec![
-        //"filter=\"\"",
-        //"since=\"\"",
-        "full_state=true",
-        "set_presence=online",
-        //"timeout=100",
+        &presence[..],
     ];
 
-    let token = &holder.token.clone()[..];
+    let filter = ["filter=", filter].join("");
+    if filter.len() > "filter=".len() {
+        params.push(&filter[..]);
+    }
 
-    let since = ["since=", &holder.next_batch[..]].join("");
-    if holder.next_batch != "" {
+    if full_state {
+        params.push("full_state=true");
+    }
+
+    let since = ["since=", since].join("");
+    if since.len() > "since=".len() {
         params.push(&since[..]);
     }
 
-    let res = holder.server.get_data_token("sync", params, token);
-    // Get joined rooms.
-    for (room_id, room) in res["rooms"]["join"].entries() {
-        let room_exists = !holder.rooms.contains_key(&room_id.to_string());
+    let wait = ["timeout=", &timeout.to_string()].join("");
+    params.push(&wait[..]);
 
-        let mut new_room = RoomData {
-            alias: String::new(),
-            members: vec![],
-            messages: vec![],
-            unread_msgs: room["unread_notifications"]["notification_count"]
-                .as_u32().unwrap()
-        };
+    // The server holds the request until there are events or `timeout` ends.
+    let limit = if full_state {
+        FULL_SYNC_TIMEOUT
+    } else {
+        Duration::from_millis(timeout) + SYNC_MARGIN
+    };
+    srv.get_data_timeout("sync", params, token, limit)
+}
 
-        // Get messages.
-        for event in room["timeline"]["events"].members() {
-            if event["type"] == "m.room.message"
-                && event["content"]["msgtype"] == "m.text" {
-                let msg = Message {
-                    content: event["content"]["body"].to_string(),
-                    msgtype: "text".to_string(),
-                    sender: event["sender"].to_string(),
-                    room: room_id.to_string()
-                };
+/// Asks for up to `limit` room events before the `from` token, newest first.
+pub fn messages(srv: &Server, token: &str, room_id: &str, from: &str,
+    limit: u32) -> ClientResult<JsonValue> {
+    let filter = json::object! {
+        "types": TIMELINE_TYPES,
+        "lazy_load_members": true
+    };
 
-                if room_exists {
-                    new_room.messages.push(msg);
-                } else {
-                    let previous_room = holder.rooms
-                        .get_mut(&room_id.to_string()).unwrap();
-                    previous_room.messages.push(msg);
-                }
-            }
-        }
+    let from = ["from=", &encode(from)].join("");
+    let limit = ["limit=", &limit.to_string()].join("");
+    let filter = ["filter=", &encode(&filter.dump())].join("");
+    srv.get_data_token(&["rooms", &encode(room_id), "messages"].join("/"),
+        vec!["dir=b", &from, &limit, &filter], token)
+}
 
-        // Get state.
-        for event in room["state"]["events"].members() {
-            if event["type"] == "m.room.member" &&
-                event["content"]["membership"] == "join" {
-
-                // Add the room to the user's list.
-                let user_id = event["sender"].to_string().clone();
-                let user_data = holder.users.get(&user_id[..]);
-                if user_data.is_none() {
-                    let alias = event["content"]["displayname"]
-                        .to_string().clone();
-
-                    let new_user = UserData {
-                        name: alias,
-                        rooms: vec![room_id.to_string().clone()],
-                        is_online: false,
-                    };
-
-                    holder.users.insert(user_id.clone(), new_user);
-                } else if !holder.users.get(&user_id[..]).unwrap()
-                    .rooms.contains(&room_id.to_string()) {
-                    holder.users.get_mut(&user_id[..]).unwrap().rooms
-                        .push(room_id.to_string().clone());
-                }
+/// Joins a room, accepting the invite to it if there is one.
+pub fn join_room(srv: &Server, token: &str, room_id: &str)
+    -> ClientResult<JsonValue> {
+    srv.post_data_token(&["join", &encode(room_id)].join("/"), "{}", token)
+}
 
-                new_room.members.push(user_id);
-            }
-        }
+/// Leaves a room, or rejects the invite to it.
+pub fn leave_room(srv: &Server, token: &str, room_id: &str)
+    -> ClientResult<JsonValue> {
+    srv.post_data_token(&["rooms", &encode(room_id), "leave"].join("/"), "{}",
+        token)
+}
 
-        holder.rooms.entry(room_id.to_string()).or_insert(new_room);
+/// Invites a user to a room.
+pub fn invite_user(srv: &Server, token: &str, room_id: &str, user_id: &str)
+    -> ClientResult<JsonValue> {
+    let data = json::object! { "user_id": user_id };
+    srv.post_data_token(&["rooms", &encode(room_id), "invite"].join("/"),
+        &data.dump(), token)
+}
+
+/// Kicks a user from a room.
+pub fn kick_user(srv: &Server, token: &str, room_id: &str, user_id: &str,
+    reason: &str) -> ClientResult<JsonValue> {
+    membership_request(srv, token, room_id, "kick", user_id, reason)
+}
+
+/// Bans a user from a room.
+pub fn ban_user(srv: &Server, token: &str, room_id: &str, user_id: &str,
+    reason: &str) -> ClientResult<JsonValue> {
+    membership_request(srv, token, room_id, "ban", user_id, reason)
+}
+
+/// Changes the membership of another user in a room.
+fn membership_request(srv: &Server, token: &str, room_id: &str, action: &str,
+    user_id: &str, reason: &str) -> ClientResult<JsonValue> {
+    let mut data = json::object! { "user_id": user_id };
+    if !reason.is_empty() {
+        data["reason"] = reason.into();
     }
+    srv.post_data_token(&["rooms", &encode(room_id), action].join("/"),
+        &data.dump(), token)
+}
+
+/// Sends an `m.room.message` event to a room.
+pub fn send_room_message(srv: &Server, token: &str, room_id: &str,
+    content: &JsonValue) -> ClientResult<JsonValue> {
+    srv.post_data_token(
+        &["rooms", &encode(room_id), "send/m.room.message"].join("/"),
+        &content.dump(), token)
+}
+
+/// Moves the fully read marker and the read receipt of the user to an event.
+pub fn set_read_markers(srv: &Server, token: &str, room_id: &str,
+    event_id: &str) -> ClientResult<JsonValue> {
+    let data = json::object! {
+        "m.fully_read": event_id,
+        "m.read": event_id,
+    };
+    srv.post_data_token(&["rooms", &encode(room_id), "read_markers"].join("/"),
+        &data.dump(), token)
+}
 
-    // Get invites.
-    for (room_id, _) in res["rooms"]["invite"].entries() {
-        holder.room_invites.push(room_id.to_string())
+/// Tells the room whether the user is typing. The notice expires after
+/// `timeout` milliseconds unless it is sent again.
+pub fn set_typing(srv: &Server, token: &str, room_id: &str, user_id: &str,
+    typing: bool, timeout: u64) -> ClientResult<JsonValue> {
+    let mut data = json::object! { "typing": typing };
+    if typing {
+        data["timeout"] = timeout.into();
     }
+    srv.put_data_token(&["rooms", &encode(room_id), "typing", &encode(user_id)]
+        .join("/"), &data.dump(), token)
+}
+
+/// Changes the presence and status message of the user.
+pub fn set_presence(srv: &Server, token: &str, user_id: &str,
+    presence: Presence, status_msg: &str) -> ClientResult<JsonValue> {
+    let data = json::object! {
+        "presence": presence.as_str(),
+        "status_msg": status_msg,
+    };
+    srv.put_data_token(&["presence", &encode(user_id), "status"].join("/"),
+        &data.dump(), token)
+}
+
+/// Reacts to an event with a key, usually an emoji.
+pub fn send_reaction(srv: &Server, token: &str, room_id: &str, event_id: &str,
+    key: &str) -> ClientResult<JsonValue> {
+    let data = json::object! {
+        "m.relates_to": {
+            "rel_type": "m.annotation",
+            "event_id": event_id,
+            "key": key,
+        },
+    };
+    srv.put_data_token(&["rooms", &encode(room_id), "send/m.reaction",
+        &transaction_id()].join("/"), &data.dump(), token)
+}
+
+/// Deletes the content of an event.
+pub fn redact_event(srv: &Server, token: &str, room_id: &str, event_id: &str)
+    -> ClientResult<JsonValue> {
+    srv.put_data_token(&["rooms", &encode(room_id), "redact",
+        &encode(event_id), &transaction_id()].join("/"), "{}", token)
+}
 
-    holder.next_batch = res["next_batch"].to_string();
+/// Unique ID for a request that sends an event, so it is not sent twice if
+/// the request is repeated.
+fn transaction_id() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0);
+    [time.to_string(), COUNTER.fetch_add(1, AtomicOrdering::SeqCst)
+        .to_string()].join(".")
+}
+
+/// Changes the display name of the user.
+pub fn set_display_name(srv: &Server, token: &str, user_id: &str, name: &str)
+    -> ClientResult<JsonValue> {
+    let data = json::object! { "displayname": name };
+    srv.put_data_token(&["profile", &encode(user_id), "displayname"]
+        .join("/"), &data.dump(), token)
+}
 
-    res
+/// Sends a state event with an empty state key to a room.
+pub fn send_state(srv: &Server, token: &str, room_id: &str, event_type: &str,
+    content: &JsonValue) -> ClientResult<JsonValue> {
+    srv.put_data_token(&["rooms", &encode(room_id), "state", event_type]
+        .join("/"), &content.dump(), token)
 }
 
-// Logins in a server given a user nama and password pair.
-pub fn login(srv: &Server, user: &str, pass: &str) -> json::JsonValue {
-    let login_request = json::object!{
+/// Percent-encodes text to use it in an URL.
+pub fn encode(text: &str) -> String {
+    Easy::new().url_encode(text.as_bytes())
+}
+
+// Logins in a server given a user nama and password pair. If a device ID is
+// given it is reused instead of creating a new device.
+pub fn login(srv: &Server, user: &str, pass: &str, device_id: &str)
+    -> ClientResult<JsonValue> {
+    let mut login_request = json::object!{
         "type": "m.login.password",
         "identifier": {
             "type": "m.id.user",
             "user": user
         },
-        "password": pass
+        "password": pass,
+        "initial_device_display_name": "Determinant"
     };
+    if !device_id.is_empty() {
+        login_request["device_id"] = device_id.into();
+    }
 
     srv.post_data("login", &json::stringify(login_request)[..])
 }
+
+/// Asks the server who the owner of a token is.
+pub fn whoami(srv: &Server, token: &str) -> ClientResult<JsonValue> {
+    srv.get_data_token("account/whoami", vec![], token)
+}
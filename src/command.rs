@@ -0,0 +1,529 @@
+/* 
+ * Determinant: a matrix CLI client inspired by VIM.
+ * Copyright (C) 2021  Sergio Miguéns Iglesias <sergio@lony.xyz>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::app::App;
+use crate::client::{ban_user, invite_user, join_room, kick_user, leave_room,
+    send_room_message, send_state, set_display_name, set_presence, DataHolder,
+    Message, Presence};
+use crate::markdown::to_html;
+use json::JsonValue;
+
+/// Commands understood by the command line, used for completion.
+pub const COMMANDS: &[&str] = &[
+    "invite",
+    "join",
+    "leave",
+    "nick",
+    "presence",
+    "q",
+    "quit",
+    "sync",
+    "topic",
+    "vsplit",
+];
+
+/// Text typed in the command line, with the history of the commands run.
+#[derive(Default)]
+pub struct CommandLine {
+    pub text: String,
+
+    history: Vec<String>,
+    /// Entry of the history being shown, the typed text is kept in `draft`.
+    history_pos: Option<usize>,
+    draft: String,
+
+    /// Candidates for the word being completed and the one being shown.
+    completions: Vec<String>,
+    completion_pos: usize,
+}
+
+impl CommandLine {
+    pub fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.reset();
+    }
+
+    pub fn pop(&mut self) -> Option<char> {
+        self.reset();
+        self.text.pop()
+    }
+
+    /// Empties the line without running it.
+    pub fn clear(&mut self) {
+        self.text = String::new();
+        self.reset();
+    }
+
+    /// Empties the line, keeping its text in the history.
+    pub fn take(&mut self) -> String {
+        let text = std::mem::take(&mut self.text);
+        self.reset();
+        let text = text.trim().to_string();
+        if !text.is_empty() && self.history.last() != Some(&text) {
+            self.history.push(text.clone());
+        }
+        text
+    }
+
+    /// Shows the previous command of the history.
+    pub fn history_prev(&mut self) {
+        let pos = match self.history_pos {
+            Some(0) => return,
+            Some(pos) => pos - 1,
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.text.clone();
+                self.history.len() - 1
+            }
+        };
+        self.history_pos = Some(pos);
+        self.text = self.history[pos].clone();
+        self.completions.clear();
+    }
+
+    /// Shows the next command of the history, or the typed text after the
+    /// last one.
+    pub fn history_next(&mut self) {
+        let pos = match self.history_pos {
+            Some(pos) => pos + 1,
+            None => return,
+        };
+        if pos < self.history.len() {
+            self.history_pos = Some(pos);
+            self.text = self.history[pos].clone();
+        } else {
+            self.history_pos = None;
+            self.text = std::mem::take(&mut self.draft);
+        }
+        self.completions.clear();
+    }
+
+    /// Completes the last word of the line. Completing again cycles through
+    /// the candidates.
+    pub fn complete(&mut self, holder: &DataHolder) {
+        let start = self.text.rfind(' ').map(|i| i + 1).unwrap_or(0);
+
+        if self.completions.is_empty() {
+            let word = &self.text[start..];
+            let mut candidates: Vec<String> = if start == 0 {
+                COMMANDS.iter().map(|name| name.to_string()).collect()
+            } else {
+                match self.text.split(' ').next().unwrap_or("") {
+                    "join" | "leave" => holder.rooms.iter()
+                        .map(|(room_id, room)| if room.alias.is_empty() {
+                            room_id.clone()
+                        } else {
+                            room.alias.clone()
+                        })
+                        .collect(),
+                    "invite" => holder.users.keys().cloned().collect(),
+                    "presence" if !self.text[..start - 1].contains(' ') => {
+                        ["online", "unavailable", "offline"].iter()
+                            .map(|state| state.to_string())
+                            .collect()
+                    }
+                    _ => vec![],
+                }
+            };
+            candidates.retain(|candidate| candidate.starts_with(word));
+            candidates.sort();
+            if candidates.is_empty() {
+                return;
+            }
+            self.completions = candidates;
+            self.completion_pos = 0;
+        } else {
+            self.completion_pos = (self.completion_pos + 1)
+                % self.completions.len();
+        }
+
+        self.text.truncate(start);
+        self.text.push_str(&self.completions[self.completion_pos]);
+    }
+
+    /// Forgets the completion and history position after an edit.
+    fn reset(&mut self) {
+        self.history_pos = None;
+        self.completions.clear();
+    }
+}
+
+/// Runs a command typed in the command line. Errors are shown in the status
+/// bar.
+pub fn run_command(app: &mut App, line: &str) {
+    let (name, args) = split_first_word(line);
+
+    let res = match name {
+        "" => Ok(()),
+        "join" => join(app, args),
+        "leave" => leave(app, args),
+        "invite" => invite(app, args),
+        "nick" => nick(app, args),
+        "presence" => presence(app, args),
+        "topic" => topic(app, args),
+        "q" | "quit" => {
+            app.close_window();
+            Ok(())
+        }
+        "vsplit" | "vs" => {
+            app.add_window();
+            Ok(())
+        }
+        "sync" => {
+            app.needs_sync = true;
+            Ok(())
+        }
+        _ => Err(["Not a command: ", name].join("")),
+    };
+
+    if let Err(e) = res {
+        app.error = e;
+    }
+}
+
+/// Command typed in the message composer, like `/me waves`.
+pub struct SlashCommand {
+    pub name: &'static str,
+    /// Arguments of the command, the ones between `<>` are required.
+    pub usage: &'static str,
+    /// Runs the command with the text after its name.
+    pub run: fn(&mut App, &str) -> Result<(), String>,
+}
+
+/// Commands that can be used from the message composer. A message starting
+/// with `//` is sent as is, without the first slash.
+pub const SLASH_COMMANDS: &[SlashCommand] = &[
+    SlashCommand { name: "me", usage: "<action>", run: me },
+    SlashCommand { name: "notice", usage: "<text>", run: notice },
+    SlashCommand { name: "plain", usage: "<text>", run: plain },
+    SlashCommand { name: "shrug", usage: "[text]", run: shrug },
+    SlashCommand { name: "rainbow", usage: "<text>", run: rainbow },
+    SlashCommand { name: "join", usage: "<room>", run: join },
+    SlashCommand { name: "part", usage: "[room]", run: leave },
+    SlashCommand { name: "invite", usage: "<user>", run: invite },
+    SlashCommand { name: "kick", usage: "<user> [reason]", run: kick },
+    SlashCommand { name: "ban", usage: "<user> [reason]", run: ban },
+    SlashCommand { name: "topic", usage: "[text|-delete]", run: topic },
+    SlashCommand { name: "nick", usage: "<name>", run: nick },
+];
+
+/// Runs a slash command, `line` is the message without the slash.
+pub fn run_slash_command(app: &mut App, line: &str) -> Result<(), String> {
+    let (name, args) = split_first_word(line);
+    let command = SLASH_COMMANDS.iter()
+        .find(|command| command.name == name)
+        .ok_or_else(|| ["Unknown command /", name, ", use // to send a \
+            message starting with /"].join(""))?;
+    if args.is_empty() && command.usage.starts_with('<') {
+        return Err(["Usage: /", command.name, " ", command.usage].join(""));
+    }
+    (command.run)(app, args)
+}
+
+/// Sends a message with the given type and text to a room. The text is
+/// formatted as Markdown unless it is disabled in the configuration.
+pub fn send_text(app: &mut App, room_id: &str, msgtype: &str, body: &str)
+    -> Result<(), String> {
+    let html = if app.config.markdown {
+        to_html(body)
+    } else {
+        None
+    };
+    send_formatted(app, room_id, msgtype, body, html)
+}
+
+/// Sends a message with an optional HTML version of the text.
+fn send_formatted(app: &mut App, room_id: &str, msgtype: &str, body: &str,
+    html: Option<String>) -> Result<(), String> {
+    let mut content = json::object! {
+        "msgtype": msgtype,
+        "body": body,
+    };
+    if let Some(html) = html {
+        content["format"] = "org.matrix.custom.html".into();
+        content["formatted_body"] = html.into();
+    }
+    send_content(app, room_id, content)
+}
+
+/// Sends a message to a room. If the selected window is replying to or
+/// editing a message, the content is turned into the reply or the edit.
+fn send_content(app: &mut App, room_id: &str, mut content: JsonValue)
+    -> Result<(), String> {
+    let window = &app.windows[app.selected_window];
+    let messages = app.holder.rooms.get(room_id)
+        .map(|room| room.messages.as_slice())
+        .unwrap_or_default();
+
+    if !window.editing.is_empty() {
+        let mut new_content = content.clone();
+        new_content.remove("m.relates_to");
+        content["body"] = ["* ", content["body"].as_str().unwrap_or("")]
+            .join("").into();
+        if let Some(html) = content["formatted_body"].as_str() {
+            content["formatted_body"] = ["* ", html].join("").into();
+        }
+        content["m.new_content"] = new_content;
+        content["m.relates_to"] = json::object! {
+            "rel_type": "m.replace",
+            "event_id": window.editing.as_str(),
+        };
+    } else if let Some(msg) = messages.iter()
+        .find(|msg| !window.reply_to.is_empty()
+            && msg.event_id == window.reply_to) {
+        // Clients without reply support show the quoted message instead.
+        let mut fallback = String::new();
+        for (i, line) in msg.content.body().lines().enumerate() {
+            if i == 0 {
+                fallback.push_str(&["> <", &msg.sender, "> ", line, "\n"]
+                    .join(""));
+            } else {
+                fallback.push_str(&["> ", line, "\n"].join(""));
+            }
+        }
+        content["body"] = [&fallback, "\n", content["body"].as_str()
+            .unwrap_or("")].join("").into();
+        if let Some(html) = content["formatted_body"].as_str() {
+            let html = [&reply_fallback_html(room_id, msg), html].join("");
+            content["formatted_body"] = html.into();
+        }
+        content["m.relates_to"] = json::object! {
+            "m.in_reply_to": {
+                "event_id": msg.event_id.as_str(),
+            },
+        };
+    }
+
+    send_room_message(&app.holder.server, &app.holder.token, room_id,
+        &content)
+        .map_err(|e| e.to_string())?;
+
+    let window = &mut app.windows[app.selected_window];
+    window.reply_to = String::new();
+    window.editing = String::new();
+    Ok(())
+}
+
+/// Quote of the message being replied to for the HTML of the reply.
+fn reply_fallback_html(room_id: &str, msg: &Message) -> String {
+    let quote = if msg.html.is_empty() {
+        msg.content.body().chars().map(escape_html).collect::<String>()
+            .replace('\n', "<br />")
+    } else {
+        // The message can be a reply itself, its quote is not repeated.
+        let html = &msg.html;
+        html.find("</mx-reply>")
+            .map_or(html.as_str(), |i| &html[i + "</mx-reply>".len()..])
+            .to_string()
+    };
+    ["<mx-reply><blockquote><a href=\"https://matrix.to/#/", room_id, "/",
+        &msg.event_id, "\">In reply to</a> <a href=\"https://matrix.to/#/",
+        &msg.sender, "\">", &msg.sender, "</a><br />", &quote,
+        "</blockquote></mx-reply>"].join("")
+}
+
+/// Room shown in the selected window.
+fn current_room(app: &App) -> Result<String, String> {
+    let room_id = &app.windows[app.selected_window].selected_room_id;
+    if room_id.is_empty() {
+        return Err(String::from("Not in a room"));
+    }
+    Ok(room_id.clone())
+}
+
+fn join(app: &mut App, args: &str) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(String::from("Usage: join <room>"));
+    }
+    join_room(&app.holder.server, &app.holder.token, args)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Leaves the given room, or the one in the selected window.
+fn leave(app: &mut App, args: &str) -> Result<(), String> {
+    let room_id = if args.is_empty() {
+        current_room(app)?
+    } else {
+        // Aliases of joined rooms are known, the ID is needed to leave.
+        app.holder.rooms.iter()
+            .find(|(_, room)| room.alias == args)
+            .map(|(room_id, _)| room_id.clone())
+            .unwrap_or_else(|| args.to_string())
+    };
+
+    leave_room(&app.holder.server, &app.holder.token, &room_id)
+        .map_err(|e| e.to_string())?;
+    for window in app.windows.iter_mut() {
+        if window.selected_room_id == room_id {
+            window.selected_room_id = String::new();
+            window.scroll.set(0);
+        }
+    }
+    Ok(())
+}
+
+fn invite(app: &mut App, args: &str) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(String::from("Usage: invite <user>"));
+    }
+    let room_id = current_room(app)?;
+    invite_user(&app.holder.server, &app.holder.token, &room_id, args)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn nick(app: &mut App, args: &str) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(String::from("Usage: nick <name>"));
+    }
+    set_display_name(&app.holder.server, &app.holder.token,
+        &app.holder.user_id, args)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Sets the presence of the user, with an optional status message.
+fn presence(app: &mut App, args: &str) -> Result<(), String> {
+    let (state, status_msg) = split_first_word(args);
+    let state = Presence::parse(state).ok_or_else(|| String::from(
+        "Usage: presence online|unavailable|offline [status]"))?;
+
+    set_presence(&app.holder.server, &app.holder.token, &app.holder.user_id,
+        state, status_msg)
+        .map_err(|e| e.to_string())?;
+    // The sync sets the presence too, the next request uses the new one.
+    app.presence = state;
+    Ok(())
+}
+
+/// Changes the topic of the room in the selected window. Without text the
+/// topic is shown, and `-delete` removes it.
+fn topic(app: &mut App, args: &str) -> Result<(), String> {
+    let room_id = current_room(app)?;
+    if args.is_empty() {
+        let topic = app.holder.rooms.get(&room_id)
+            .map(|room| room.topic.as_str())
+            .unwrap_or("");
+        app.message = if topic.is_empty() {
+            String::from("No topic set")
+        } else {
+            ["Topic: ", topic].join("")
+        };
+        return Ok(());
+    }
+
+    let topic = if args == "-delete" { "" } else { args };
+    let content = json::object! { "topic": topic };
+    send_state(&app.holder.server, &app.holder.token, &room_id,
+        "m.room.topic", &content)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn me(app: &mut App, args: &str) -> Result<(), String> {
+    let room_id = current_room(app)?;
+    send_text(app, &room_id, "m.emote", args)
+}
+
+fn notice(app: &mut App, args: &str) -> Result<(), String> {
+    let room_id = current_room(app)?;
+    send_text(app, &room_id, "m.notice", args)
+}
+
+/// Sends the text as it is, without Markdown formatting.
+fn plain(app: &mut App, args: &str) -> Result<(), String> {
+    let room_id = current_room(app)?;
+    send_formatted(app, &room_id, "m.text", args, None)
+}
+
+/// Sends the text after a shrug, without formatting so the backslash in it
+/// is kept.
+fn shrug(app: &mut App, args: &str) -> Result<(), String> {
+    let room_id = current_room(app)?;
+    let text = ["¯\\_(ツ)_/¯", args].join(" ");
+    send_formatted(app, &room_id, "m.text", text.trim_end(), None)
+}
+
+/// Sends the text with each letter in a colour of the rainbow.
+fn rainbow(app: &mut App, args: &str) -> Result<(), String> {
+    let room_id = current_room(app)?;
+    let count = args.chars().count();
+    let mut html = String::new();
+    for (i, c) in args.chars().enumerate() {
+        if c.is_whitespace() {
+            html.push(c);
+            continue;
+        }
+        let (r, g, b) = hue_to_rgb(i as f64/count as f64);
+        html.push_str(&format!("<font color=\"#{:02x}{:02x}{:02x}\">{}</font>",
+            r, g, b, escape_html(c)));
+    }
+
+    send_formatted(app, &room_id, "m.text", args, Some(html))
+}
+
+fn kick(app: &mut App, args: &str) -> Result<(), String> {
+    let room_id = current_room(app)?;
+    let (user_id, reason) = split_first_word(args);
+    kick_user(&app.holder.server, &app.holder.token, &room_id, user_id,
+        reason)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn ban(app: &mut App, args: &str) -> Result<(), String> {
+    let room_id = current_room(app)?;
+    let (user_id, reason) = split_first_word(args);
+    ban_user(&app.holder.server, &app.holder.token, &room_id, user_id,
+        reason)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Splits the first word from the rest of the text.
+fn split_first_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// Colour with full saturation for a hue between 0 and 1.
+fn hue_to_rgb(hue: f64) -> (u8, u8, u8) {
+    let h = hue*6.0;
+    let x = ((1.0 - (h%2.0 - 1.0).abs())*255.0) as u8;
+    match h as u32 {
+        0 => (255, x, 0),
+        1 => (x, 255, 0),
+        2 => (0, 255, x),
+        3 => (0, x, 255),
+        4 => (x, 0, 255),
+        _ => (255, 0, x),
+    }
+}
+
+fn escape_html(c: char) -> String {
+    match c {
+        '&' => String::from("&amp;"),
+        '<' => String::from("&lt;"),
+        '>' => String::from("&gt;"),
+        '"' => String::from("&quot;"),
+        c => c.to_string(),
+    }
+}
@@ -0,0 +1,443 @@
+/* 
+ * Determinant: a matrix CLI client inspired by VIM.
+ * Copyright (C) 2021  Sergio Miguéns Iglesias <sergio@lony.xyz>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std:: {
+    error,
+    fmt,
+    fs,
+    io,
+    path::PathBuf,
+};
+use toml::{value::Table, Value};
+use crate::client::Presence;
+use chrono::format::{Item, StrftimeItems};
+use tui::style::Color;
+
+/// User configuration, read from `$XDG_CONFIG_HOME/determinant/config`.
+///
+/// The file is in TOML format and every value is optional:
+///
+/// ```toml
+/// [server]
+/// homeserver = "https://matrix.org"
+/// presence = "online"
+///
+/// [interface]
+/// tick_rate = 250
+/// invites_height = 10
+/// show_state_events = true
+/// markdown = true
+/// time_format = "%H:%M"
+/// day_format = "%A %-d %B"
+/// utc = false
+///
+/// [colors]
+/// border = "white"
+/// selected_border = "red"
+/// text = "white"
+/// status_fg = "black"
+/// status_bg = "white"
+/// insert_bg = "green"
+/// code = "yellow"
+/// nicks = ["lightred", "lightgreen", "lightyellow", "lightblue"]
+///
+/// [keys]
+/// insert = "i"
+/// split = "v"
+/// sync = "s"
+/// quit = "q"
+/// accept = "a"
+/// reject = "d"
+/// reply = "r"
+/// edit = "e"
+/// react = "+"
+/// ```
+pub struct Config {
+    /// Homeserver shown in the login screen.
+    pub homeserver: String,
+    /// Presence set when the client starts.
+    pub presence: Presence,
+
+    /// Milliseconds between terminal ticks.
+    pub tick_rate: u64,
+    pub invites_height: u16,
+    /// Show joins, leaves, topic changes and other state in the timeline.
+    pub show_state_events: bool,
+    /// Format sent messages written in Markdown.
+    pub markdown: bool,
+    /// `strftime` format of the time shown next to messages.
+    pub time_format: String,
+    /// `strftime` format of the date in the separators between days.
+    pub day_format: String,
+    /// Show times in UTC instead of the local time zone.
+    pub utc: bool,
+
+    pub colors: ColorConfig,
+    pub keys: KeyConfig,
+}
+
+/// Colours used to draw the interface.
+pub struct ColorConfig {
+    pub border: Color,
+    pub selected_border: Color,
+    pub text: Color,
+    pub status_fg: Color,
+    pub status_bg: Color,
+    pub insert_bg: Color,
+    /// Code in formatted messages.
+    pub code: Color,
+    /// Palette the colour of each user name is picked from.
+    pub nicks: Vec<Color>,
+}
+
+impl ColorConfig {
+    /// Colour of the name of a user. It is picked from the palette with a
+    /// hash of the user ID, the same way other Matrix clients do, so it does
+    /// not change between sessions.
+    pub fn nick_color(&self, user_id: &str) -> Color {
+        if self.nicks.is_empty() {
+            return self.text;
+        }
+        let hash = user_id.encode_utf16()
+            .fold(0i32, |hash, c| hash.wrapping_mul(31).wrapping_add(c as i32));
+        self.nicks[hash.unsigned_abs() as usize % self.nicks.len()]
+    }
+}
+
+/// Keys for the Normal mode commands.
+pub struct KeyConfig {
+    pub insert: char,
+    pub split: char,
+    pub sync: char,
+    pub quit: char,
+    /// Accept the selected invite.
+    pub accept: char,
+    /// Reject the selected invite.
+    pub reject: char,
+    /// Reply to the selected message.
+    pub reply: char,
+    /// Edit the selected message.
+    pub edit: char,
+    /// React to the selected message.
+    pub react: char,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            homeserver: String::new(),
+            presence: Presence::Online,
+            tick_rate: 250,
+            invites_height: 10,
+            show_state_events: true,
+            markdown: true,
+            time_format: String::from("%H:%M"),
+            day_format: String::from("%A %-d %B"),
+            utc: false,
+            colors: ColorConfig {
+                border: Color::White,
+                selected_border: Color::Red,
+                text: Color::White,
+                status_fg: Color::Black,
+                status_bg: Color::White,
+                insert_bg: Color::Green,
+                code: Color::Yellow,
+                nicks: vec![
+                    Color::LightRed,
+                    Color::LightGreen,
+                    Color::LightYellow,
+                    Color::LightBlue,
+                    Color::LightMagenta,
+                    Color::LightCyan,
+                    Color::Red,
+                    Color::Green,
+                ],
+            },
+            keys: KeyConfig {
+                insert: 'i',
+                split: 'v',
+                sync: 's',
+                quit: 'q',
+                accept: 'a',
+                reject: 'd',
+                reply: 'r',
+                edit: 'e',
+                react: '+',
+            },
+        }
+    }
+}
+
+/// Errors found while loading the configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file exists but could not be read.
+    Read(PathBuf, io::Error),
+    /// The file is not valid TOML.
+    Parse(PathBuf, toml::de::Error),
+    /// A value is unknown or has the wrong type.
+    Invalid(PathBuf, String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "could not read {}: {}",
+                path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "syntax error in {}: {}",
+                path.display(), e),
+            ConfigError::Invalid(path, key, reason) => write!(f,
+                "invalid option `{}` in {}: {}", key, path.display(), reason),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+impl Config {
+    /// Path of the configuration file.
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("determinant").join("config"))
+    }
+
+    /// Loads the configuration file. A missing file is not an error, the
+    /// default configuration is used instead.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).map_err(|e| match e {
+                ConfigError::Parse(_, e) => ConfigError::Parse(path, e),
+                ConfigError::Invalid(_, key, reason) =>
+                    ConfigError::Invalid(path, key, reason),
+                e => e,
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            Err(e) => Err(ConfigError::Read(path, e)),
+        }
+    }
+
+    /// Parses the text of a configuration file.
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let value: Value = text.parse()
+            .map_err(|e| ConfigError::Parse(PathBuf::new(), e))?;
+        let mut config = Self::default();
+
+        for (section, values) in value.as_table().unwrap() {
+            let values = match values.as_table() {
+                Some(values) => values,
+                None => return Err(invalid(section, "expected a section")),
+            };
+
+            match &section[..] {
+                "server" => config.parse_server(values)?,
+                "interface" => config.parse_interface(values)?,
+                "colors" => config.parse_colors(values)?,
+                "keys" => config.parse_keys(values)?,
+                _ => return Err(invalid(section, "unknown section")),
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn parse_server(&mut self, values: &Table) -> Result<(), ConfigError> {
+        for (key, value) in values {
+            let name = ["server.", key].join("");
+            match &key[..] {
+                "homeserver" => self.homeserver = as_str(&name, value)?,
+                "presence" => {
+                    self.presence = Presence::parse(&as_str(&name, value)?)
+                        .ok_or_else(|| invalid(&name, "expected online, \
+                            unavailable or offline"))?;
+                }
+                _ => return Err(invalid(&name, "unknown option")),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_interface(&mut self, values: &Table) -> Result<(), ConfigError> {
+        for (key, value) in values {
+            let name = ["interface.", key].join("");
+            match &key[..] {
+                "tick_rate" => {
+                    self.tick_rate = as_int(&name, value, 1, 60_000)?;
+                }
+                "invites_height" => {
+                    self.invites_height = as_int(&name, value, 0,
+                        u16::MAX as u64)? as u16;
+                }
+                "show_state_events" => {
+                    self.show_state_events = as_bool(&name, value)?;
+                }
+                "markdown" => {
+                    self.markdown = as_bool(&name, value)?;
+                }
+                "time_format" => {
+                    self.time_format = as_time_format(&name, value)?;
+                }
+                "day_format" => {
+                    self.day_format = as_time_format(&name, value)?;
+                }
+                "utc" => self.utc = as_bool(&name, value)?,
+                _ => return Err(invalid(&name, "unknown option")),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_colors(&mut self, values: &Table) -> Result<(), ConfigError> {
+        for (key, value) in values {
+            let name = ["colors.", key].join("");
+            if key == "nicks" {
+                self.colors.nicks = as_palette(&name, value)?;
+                continue;
+            }
+            let color = as_color(&name, value)?;
+            match &key[..] {
+                "border" => self.colors.border = color,
+                "selected_border" => self.colors.selected_border = color,
+                "text" => self.colors.text = color,
+                "status_fg" => self.colors.status_fg = color,
+                "status_bg" => self.colors.status_bg = color,
+                "insert_bg" => self.colors.insert_bg = color,
+                "code" => self.colors.code = color,
+                _ => return Err(invalid(&name, "unknown option")),
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_keys(&mut self, values: &Table) -> Result<(), ConfigError> {
+        for (key, value) in values {
+            let name = ["keys.", key].join("");
+            let c = as_key(&name, value)?;
+            match &key[..] {
+                "insert" => self.keys.insert = c,
+                "split" => self.keys.split = c,
+                "sync" => self.keys.sync = c,
+                "quit" => self.keys.quit = c,
+                "accept" => self.keys.accept = c,
+                "reject" => self.keys.reject = c,
+                "reply" => self.keys.reply = c,
+                "edit" => self.keys.edit = c,
+                "react" => self.keys.react = c,
+                _ => return Err(invalid(&name, "unknown option")),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds an error for a bad option. The path is filled in by the caller.
+fn invalid(key: &str, reason: &str) -> ConfigError {
+    ConfigError::Invalid(PathBuf::new(), key.to_string(), reason.to_string())
+}
+
+fn as_str(key: &str, value: &Value) -> Result<String, ConfigError> {
+    value.as_str().map(|s| s.to_string())
+        .ok_or_else(|| invalid(key, "expected a string"))
+}
+
+fn as_bool(key: &str, value: &Value) -> Result<bool, ConfigError> {
+    value.as_bool().ok_or_else(|| invalid(key, "expected true or false"))
+}
+
+/// Parses a `strftime` format, checking it can be used to format a time.
+fn as_time_format(key: &str, value: &Value) -> Result<String, ConfigError> {
+    let format = as_str(key, value)?;
+    if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+        return Err(invalid(key, "bad time format"));
+    }
+    Ok(format)
+}
+
+fn as_int(key: &str, value: &Value, min: u64, max: u64)
+    -> Result<u64, ConfigError> {
+    match value.as_integer() {
+        Some(n) if n >= min as i64 && n <= max as i64 => Ok(n as u64),
+        Some(_) => Err(invalid(key, &format!("expected a number between {} \
+            and {}", min, max))),
+        None => Err(invalid(key, "expected a number")),
+    }
+}
+
+fn as_key(key: &str, value: &Value) -> Result<char, ConfigError> {
+    let text = as_str(key, value)?;
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(invalid(key, "expected a single character")),
+    }
+}
+
+/// Parses a list of colours.
+fn as_palette(key: &str, value: &Value) -> Result<Vec<Color>, ConfigError> {
+    let values = value.as_array()
+        .ok_or_else(|| invalid(key, "expected a list of colours"))?;
+    if values.is_empty() {
+        return Err(invalid(key, "expected at least one colour"));
+    }
+    values.iter().map(|value| as_color(key, value)).collect()
+}
+
+/// Parses a colour given by name, as a `#rrggbb` value or as a number from
+/// the 256 colour palette.
+fn as_color(key: &str, value: &Value) -> Result<Color, ConfigError> {
+    if let Some(n) = value.as_integer() {
+        if (0..256).contains(&n) {
+            return Ok(Color::Indexed(n as u8));
+        }
+        return Err(invalid(key, "colour numbers go from 0 to 255"));
+    }
+
+    let name = as_str(key, value)?.to_lowercase();
+    let color = match &name.replace(&['_', '-', ' '][..], "")[..] {
+        "reset" | "default" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        hex if hex.len() == 7 && hex.starts_with('#') => {
+            let rgb = u32::from_str_radix(&hex[1..], 16)
+                .map_err(|_| invalid(key, "bad hexadecimal colour"))?;
+            Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+        }
+        _ => return Err(invalid(key, &format!("unknown colour \"{}\"",
+            name))),
+    };
+    Ok(color)
+}
@@ -0,0 +1,108 @@
+/* 
+ * Determinant: a matrix CLI client inspired by VIM.
+ * Copyright (C) 2021  Sergio Miguéns Iglesias <sergio@lony.xyz>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+/// Shortcodes of the emojis offered when reacting to a message, most used
+/// first.
+pub const EMOJIS: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("heart", "❤️"),
+    ("joy", "😂"),
+    ("tada", "🎉"),
+    ("eyes", "👀"),
+    ("pray", "🙏"),
+    ("fire", "🔥"),
+    ("rocket", "🚀"),
+    ("smile", "😄"),
+    ("wink", "😉"),
+    ("thinking", "🤔"),
+    ("cry", "😢"),
+    ("scream", "😱"),
+    ("clap", "👏"),
+    ("wave", "👋"),
+    ("ok_hand", "👌"),
+    ("muscle", "💪"),
+    ("check", "✅"),
+    ("x", "❌"),
+    ("100", "💯"),
+    ("star", "⭐"),
+    ("coffee", "☕"),
+    ("beer", "🍺"),
+    ("pizza", "🍕"),
+    ("cat", "🐱"),
+    ("dog", "🐶"),
+    ("see_no_evil", "🙈"),
+    ("facepalm", "🤦"),
+    ("shrug", "🤷"),
+];
+
+/// Picker to choose the key of a reaction by typing part of its shortcode.
+#[derive(Default)]
+pub struct EmojiPicker {
+    pub query: String,
+    /// Position of the chosen emoji in the matches.
+    pub selected: usize,
+}
+
+impl EmojiPicker {
+    /// Emojis whose shortcode contains the query.
+    pub fn matches(&self) -> Vec<(&'static str, &'static str)> {
+        EMOJIS.iter()
+            .filter(|(name, _)| name.contains(self.query.as_str()))
+            .copied()
+            .collect()
+    }
+
+    /// Key to react with: the chosen emoji, or the query itself if nothing
+    /// matches so any emoji can be typed or pasted.
+    pub fn choice(&self) -> Option<String> {
+        match self.matches().get(self.selected) {
+            Some((_, emoji)) => Some(emoji.to_string()),
+            None if !self.query.is_empty() => Some(self.query.clone()),
+            None => None,
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.selected = 0;
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.selected = 0;
+    }
+
+    pub fn clear(&mut self) {
+        self.query = String::new();
+        self.selected = 0;
+    }
+
+    /// Chooses the next match, or the previous one if `back` is set.
+    pub fn cycle(&mut self, back: bool) {
+        let count = self.matches().len();
+        if count == 0 {
+            return;
+        }
+        self.selected = if back {
+            (self.selected + count - 1) % count
+        } else {
+            (self.selected + 1) % count
+        };
+    }
+}
@@ -17,15 +17,28 @@
  */
 
 use std:: {
-    sync::mpsc,
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        mpsc,
+        Arc,
+    },
     thread,
     time::{Duration, Instant},
 };
+use json::JsonValue;
 use crate::app::AppResult;
+use crate::client::{sync_request, ClientError, ClientResult, Presence,
+    Server};
 use crossterm::event::{self, Event as CrosstermEvent, KeyEvent, MouseEvent};
 
+/// Milliseconds the server may wait for new events before answering a sync.
+pub const SYNC_TIMEOUT: u64 = 30_000;
+
+/// Milliseconds to wait before syncing again after a failed sync.
+const SYNC_RETRY: u64 = 5_000;
+
 /// Terminal events.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum Event {
     /// Terminal tick.
     Tick,
@@ -35,6 +48,8 @@ pub enum Event {
     Mouse(MouseEvent),
     /// Terminal resize.
     Resize(u16, u16),
+    /// Answer to a background sync.
+    Sync(ClientResult<JsonValue>),
 }
 
 /// Terminal event handler.
@@ -45,7 +60,13 @@ pub struct EventHandler {
     /// Event receiver channel.
     receiver: mpsc::Receiver<Event>,
     /// Event handler thread.
+    #[allow(dead_code)]
     handler: thread::JoinHandle<()>,
+    /// Incremented every time a sync thread is started, older threads stop
+    /// when they see it changed.
+    sync_generation: Arc<AtomicUsize>,
+    /// Presence sent with each sync request, it can change while syncing.
+    presence: Arc<AtomicU8>,
 }
 
 impl EventHandler {
@@ -86,9 +107,74 @@ impl EventHandler {
             sender,
             receiver,
             handler,
+            sync_generation: Arc::new(AtomicUsize::new(0)),
+            presence: Arc::new(AtomicU8::new(Presence::Offline as u8)),
         }
     }
 
+    /// Changes the presence the user is shown with while syncing, from the
+    /// next sync request.
+    pub fn set_presence(&self, presence: Presence) {
+        self.presence.store(presence as u8, Ordering::SeqCst);
+    }
+
+    /// Starts syncing with the server in the background, stopping any
+    /// previous sync thread.
+    ///
+    /// The first request answers right away, and asks for the full state of
+    /// the rooms if there is no `since` token yet. The following ones
+    /// long-poll the server for new events.
+    /// Every answer is sent as an [`Event::Sync`]. The user is shown with the
+    /// presence given to [`EventHandler::set_presence`] while syncing.
+    pub fn start_sync(&self, server: Server, token: String, since: String,
+        filter: String) {
+        let generation = self.sync_generation.fetch_add(1, Ordering::SeqCst)
+            + 1;
+        let current = self.sync_generation.clone();
+        let sender = self.sender.clone();
+        let presence = self.presence.clone();
+
+        thread::spawn(move || {
+            let mut since = since;
+            // Restarting the sync only needs the changes since the last one.
+            let mut full_state = since.is_empty();
+            let mut timeout = 0;
+            loop {
+                let presence = match presence.load(Ordering::SeqCst) {
+                    p if p == Presence::Online as u8 => Presence::Online,
+                    p if p == Presence::Unavailable as u8 => {
+                        Presence::Unavailable
+                    }
+                    _ => Presence::Offline,
+                };
+                let res = sync_request(&server, &token, &since, &filter,
+                    timeout, full_state, presence);
+
+                let wait = match &res {
+                    Ok(res) => {
+                        since = res["next_batch"].to_string();
+                        full_state = false;
+                        timeout = SYNC_TIMEOUT;
+                        0
+                    }
+                    Err(ClientError::RateLimited { retry_after_ms }) => {
+                        *retry_after_ms
+                    }
+                    Err(_) => SYNC_RETRY,
+                };
+                let logged_out = matches!(&res, Err(e)
+                    if e.errcode() == Some("M_UNKNOWN_TOKEN"));
+
+                if current.load(Ordering::SeqCst) != generation
+                    || sender.send(Event::Sync(res)).is_err()
+                    || logged_out {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(wait));
+            }
+        });
+    }
+
     /// Receive the next event from the handler thread.
     ///
     /// This function will always block the current thread if
@@ -16,101 +16,305 @@
  * this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::app::{App, AppResult, AppMode};
-use crossterm::event::{KeyCode, KeyEvent};
-use crate::client::{sync};
+use crate::app::{App, AppResult, AppMode, LOGIN_FIELD_COUNT};
+use crate::command::run_command;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
 /// Handles the key events and updates the state of [`App`].
 pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
     let window_count = app.windows.len();
-    let mut window = &mut app.windows[app.selected_window];
+    let room_count = app.holder.rooms.len();
+    let invite_count = app.holder.room_invites.len();
+    let window = &mut app.windows[app.selected_window];
+
+    // Errors and messages stay in the status bar until the next key is
+    // pressed.
+    app.error = String::new();
+    app.message = String::new();
 
     match app.mode {
-        AppMode::Normal => match key_event.code {
+        AppMode::Login => match key_event.code {
+            KeyCode::Esc => {
+                app.running = false;
+            }
+
+            KeyCode::Tab | KeyCode::Down => {
+                app.login.selected_field = (app.login.selected_field + 1)
+                    % LOGIN_FIELD_COUNT;
+            }
+
+            KeyCode::BackTab | KeyCode::Up => {
+                app.login.selected_field = (app.login.selected_field
+                    + LOGIN_FIELD_COUNT - 1) % LOGIN_FIELD_COUNT;
+            }
+
             KeyCode::Enter => {
-                if window.written_msg != "" {
-                    app.send_message();
+                if app.login.selected_field < LOGIN_FIELD_COUNT - 1 {
+                    app.login.selected_field += 1;
                 } else {
-                    app.sel_room();
+                    app.submit_login();
                 }
             }
 
-            KeyCode::Up => {
-                if window.selected_room > 0 {
+            KeyCode::Backspace => {
+                app.login.selected_mut().pop();
+            }
+
+            KeyCode::Char(c) => {
+                app.login.selected_mut().push(c);
+            }
+
+            _ => {}
+        }
+
+        AppMode::Normal => {
+            let in_room = !window.selected_room_id.is_empty();
+            let ctrl = key_event.modifiers.contains(KeyModifiers::CONTROL);
+            let half_page = (app.page_height as usize/2).max(1);
+            let pending_key = app.pending_key.take();
+            let on_invites = !in_room && app.invites_focused
+                && invite_count > 0;
+
+            match key_event.code {
+                // Scrolling inside a room.
+                KeyCode::Char('u') if in_room && ctrl => {
+                    app.scroll_up(half_page);
+                }
+
+                KeyCode::Char('d') if in_room && ctrl => {
+                    app.scroll_down(half_page);
+                }
+
+                KeyCode::Char('k') if in_room => {
+                    app.scroll_up(1);
+                }
+
+                KeyCode::Char('j') if in_room => {
+                    app.scroll_down(1);
+                }
+
+                KeyCode::Char('g') if in_room && pending_key == Some('g') => {
+                    window.scroll.set(window.max_scroll.get());
+                }
+
+                KeyCode::Char('g') if in_room => {
+                    app.pending_key = Some('g');
+                }
+
+                KeyCode::Char('G') if in_room => {
+                    window.scroll.set(0);
+                }
+
+                // Message cursor, to act on a message.
+                KeyCode::Up if in_room => {
+                    app.move_selection(true);
+                }
+
+                KeyCode::Down if in_room => {
+                    app.move_selection(false);
+                }
+
+                KeyCode::Esc if in_room => {
+                    window.selected_msg = String::new();
+                }
+
+                KeyCode::Char(c) if in_room && c == app.config.keys.reply => {
+                    app.reply_to_selected();
+                }
+
+                KeyCode::Char(c) if in_room && c == app.config.keys.edit => {
+                    app.edit_selected();
+                }
+
+                KeyCode::Char(c) if in_room && c == app.config.keys.react => {
+                    app.start_reaction();
+                }
+
+                KeyCode::Char('d') if in_room && pending_key == Some('d') => {
+                    app.redact_selected();
+                }
+
+                KeyCode::Char('d') if in_room => {
+                    app.pending_key = Some('d');
+                }
+
+                // Invites, below the room list.
+                KeyCode::Tab if !in_room && invite_count > 0 => {
+                    app.invites_focused = !app.invites_focused;
+                }
+
+                KeyCode::Up | KeyCode::Char('k') if on_invites => {
+                    app.selected_invite = app.selected_invite.saturating_sub(1);
+                }
+
+                KeyCode::Down | KeyCode::Char('j') if on_invites => {
+                    app.selected_invite = (app.selected_invite + 1)
+                        .min(invite_count - 1);
+                }
+
+                KeyCode::Enter if on_invites => {
+                    app.answer_invite(true);
+                }
+
+                KeyCode::Char(c) if on_invites
+                    && c == app.config.keys.accept => {
+                    app.answer_invite(true);
+                }
+
+                KeyCode::Char(c) if on_invites
+                    && c == app.config.keys.reject => {
+                    app.answer_invite(false);
+                }
+
+                KeyCode::Enter => {
+                    if !window.written_msg.is_empty() {
+                        app.send_message();
+                    } else {
+                        app.sel_room();
+                    }
+                }
+
+                KeyCode::Up | KeyCode::Char('k')
+                    if window.selected_room > 0 => {
                     window.selected_room -= 1;
                 }
-            }
 
-            KeyCode::Down => {
-                if window.selected_room < app.holder.rooms.keys().len() - 1 {
+                KeyCode::Down | KeyCode::Char('j')
+                    if window.selected_room + 1 < room_count => {
                     window.selected_room += 1;
                 }
-            }
 
-            KeyCode::Left => {
-                if app.selected_window > 0 {
+                KeyCode::Left if app.selected_window > 0 => {
                     app.selected_window -= 1;
                 }
-            }
 
-            KeyCode::Right => {
-                if app.selected_window < window_count - 1 {
+                KeyCode::Right if app.selected_window < window_count - 1 => {
                     app.selected_window += 1;
                 }
+
+                KeyCode::Char(c) if c == app.config.keys.insert => {
+                    app.mode = AppMode::Insert;
+                }
+
+                KeyCode::Char(c) if c == app.config.keys.split => {
+                    app.add_window();
+                }
+
+                KeyCode::Char(c) if c == app.config.keys.sync => {
+                    app.needs_sync = true;
+                }
+
+                KeyCode::Char(c) if c == app.config.keys.quit => {
+                    app.close_window();
+                }
+
+                KeyCode::Char(':') => {
+                    app.command.clear();
+                    app.mode = AppMode::Command;
+                }
+
+                _ => {}
             }
+        }
 
-            KeyCode::Char('i') => {
-                app.mode = AppMode::Insert;
+        AppMode::Command => match key_event.code {
+            KeyCode::Esc => {
+                app.command.clear();
+                app.mode = AppMode::Normal;
             }
 
-            KeyCode::Char('v') => {
-                app.add_window();
+            KeyCode::Enter => {
+                let line = app.command.take();
+                app.mode = AppMode::Normal;
+                run_command(app, &line);
             }
 
-            KeyCode::Char('s') => {
-                sync(&mut app.holder);
+            KeyCode::Tab => {
+                app.command.complete(&app.holder);
             }
 
-            KeyCode::Char('q') => {
-                if window_count > 1 && window.selected_room_id == "" {
-                    app.windows.remove(app.selected_window);
-                    if app.selected_window != 0 {
-                        app.selected_window -= 1;
-                    }
-                } else if window.selected_room_id == "" {
-                    app.running = false;
-                } else {
-                    window.written_msg = String::new();
-                    window.selected_room_id = String::new();
-                }
+            KeyCode::Up => {
+                app.command.history_prev();
+            }
+
+            KeyCode::Down => {
+                app.command.history_next();
+            }
+
+            // Deleting past the start leaves the command line, like in vim.
+            KeyCode::Backspace if app.command.text.is_empty() => {
+                app.mode = AppMode::Normal;
+            }
+
+            KeyCode::Backspace => {
+                app.command.pop();
+            }
+
+            KeyCode::Char(c) => {
+                app.command.push(c);
             }
 
             _ => {}
         }
 
-        AppMode::Insert => match key_event.code {
+        AppMode::React => match key_event.code {
             KeyCode::Esc => {
                 app.mode = AppMode::Normal;
             }
 
-            KeyCode::Backspace => {
-                if window.written_msg.len() > 0 && window.selected_char > 1 {
-                    let selected_char = window.selected_char;
-                    window.written_msg.remove(selected_char - 2);
-                    window.selected_char -= 1;
+            KeyCode::Enter => {
+                app.mode = AppMode::Normal;
+                if let Some(key) = app.emoji.choice() {
+                    app.react(&key);
                 }
             }
 
-            KeyCode::Left => {
-                if window.selected_char > 0 {
-                    window.selected_char -= 1;
-                }
+            KeyCode::Tab | KeyCode::Right => {
+                app.emoji.cycle(false);
+            }
+
+            KeyCode::BackTab | KeyCode::Left => {
+                app.emoji.cycle(true);
+            }
+
+            KeyCode::Backspace if app.emoji.query.is_empty() => {
+                app.mode = AppMode::Normal;
+            }
+
+            KeyCode::Backspace => {
+                app.emoji.pop();
+            }
+
+            KeyCode::Char(c) => {
+                app.emoji.push(c);
             }
 
-            KeyCode::Right => {
-                if window.selected_char < window.written_msg.len() {
-                    window.selected_char += 1;
+            _ => {}
+        }
+
+        AppMode::Insert => match key_event.code {
+            // Leaving an empty message also cancels the reply or edit.
+            KeyCode::Esc => {
+                if window.written_msg.is_empty() {
+                    window.reply_to = String::new();
+                    window.editing = String::new();
                 }
+                app.mode = AppMode::Normal;
+            }
+
+            KeyCode::Backspace if window.selected_char > 1 => {
+                let selected_char = window.selected_char;
+                window.written_msg.remove(selected_char - 2);
+                window.selected_char -= 1;
+            }
+
+            KeyCode::Left if window.selected_char > 0 => {
+                window.selected_char -= 1;
+            }
+
+            KeyCode::Right
+                if window.selected_char < window.written_msg.len() => {
+                window.selected_char += 1;
             }
 
             KeyCode::Enter => {
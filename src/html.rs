@@ -0,0 +1,452 @@
+/* 
+ * Determinant: a matrix CLI client inspired by VIM.
+ * Copyright (C) 2021  Sergio Miguéns Iglesias <sergio@lony.xyz>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use tui::{
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+};
+
+/// Tags that have no closing tag.
+const VOID_TAGS: &[&str] = &["br", "hr", "img"];
+
+/// Converts the HTML `formatted_body` of a message to styled lines. `style`
+/// is the style of the plain text and `code` the colour of code.
+///
+/// Returns `None` if the HTML is malformed or has no text, so the plain
+/// `body` can be shown instead.
+pub fn to_lines(html: &str, style: Style, code: Color)
+    -> Option<Vec<Spans<'static>>> {
+    let mut writer = Writer::new(style);
+    // Open tags with the style of the text before them and whether their
+    // content is hidden.
+    let mut open: Vec<(String, Style, bool)> = Vec::new();
+    let mut style = style;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let skipping = open.iter().any(|(_, _, hidden)| *hidden);
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = &comment[comment.find("-->")? + 3..];
+            continue;
+        }
+        if !rest.starts_with('<') {
+            let end = rest.find('<').unwrap_or(rest.len());
+            if !skipping {
+                writer.text(&decode(&rest[..end]), style);
+            }
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest.find('>')?;
+        let tag = Tag::parse(&rest[1..end])?;
+        rest = &rest[end + 1..];
+
+        if tag.closing {
+            // Tags like `p` and `li` can be left open, they end here too.
+            let pos = open.iter()
+                .rposition(|(name, _, _)| *name == tag.name)?;
+            while open.len() > pos {
+                let (name, old_style, hidden) = open.pop()?;
+                // Hidden tags were not opened on the writer.
+                if !hidden {
+                    writer.close(&name);
+                }
+                style = old_style;
+            }
+            continue;
+        }
+
+        if skipping {
+            if !VOID_TAGS.contains(&&tag.name[..]) && !tag.self_closing {
+                open.push((tag.name, style, true));
+            }
+            continue;
+        }
+
+        let old_style = style;
+        // Quotes of replied messages are shown by the client.
+        let mut hidden = tag.name == "mx-reply";
+        match &tag.name[..] {
+            "b" | "strong" => style = style.add_modifier(Modifier::BOLD),
+            "i" | "em" => style = style.add_modifier(Modifier::ITALIC),
+            "u" | "ins" => style = style.add_modifier(Modifier::UNDERLINED),
+            "del" | "s" | "strike" => {
+                style = style.add_modifier(Modifier::CROSSED_OUT);
+            }
+            "code" => style = style.fg(code),
+            "pre" => {
+                writer.block_break();
+                writer.pre += 1;
+                style = style.fg(code);
+            }
+            "blockquote" => {
+                writer.block_break();
+                writer.quotes += 1;
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                writer.block_break();
+                style = style.add_modifier(Modifier::BOLD);
+            }
+            "p" | "div" | "table" | "tr" => writer.block_break(),
+            "ul" => {
+                writer.block_break();
+                writer.lists.push(None);
+            }
+            "ol" => {
+                writer.block_break();
+                let start = tag.attr("start").and_then(|n| n.parse().ok());
+                writer.lists.push(Some(start.unwrap_or(1)));
+            }
+            "li" => {
+                writer.block_break();
+                writer.bullet = match writer.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        Some(format!("{}. ", *n - 1))
+                    }
+                    _ => Some(String::from("• ")),
+                };
+            }
+            "td" | "th" => writer.text(" ", style),
+            "a" => {
+                style = style.add_modifier(Modifier::UNDERLINED);
+                writer.links.push((tag.attr("href").unwrap_or("")
+                    .to_string(), String::new()));
+            }
+            // The content of spoilers is only sent in the HTML.
+            "span" if tag.attr("data-mx-spoiler").is_some() => {
+                let reason = tag.attr("data-mx-spoiler").unwrap_or("");
+                if reason.is_empty() {
+                    writer.text("[spoiler]", style);
+                } else {
+                    writer.text(&["[spoiler: ", reason, "]"].join(""), style);
+                }
+                hidden = true;
+            }
+            "font" | "span" => {
+                let fg = tag.attr("data-mx-color").or_else(|| tag.attr("color"))
+                    .and_then(parse_color);
+                if let Some(color) = fg {
+                    style = style.fg(color);
+                }
+                if let Some(color) = tag.attr("data-mx-bg-color")
+                    .and_then(parse_color) {
+                    style = style.bg(color);
+                }
+            }
+            "br" => writer.new_line(),
+            "hr" => {
+                writer.block_break();
+                writer.text("────────", old_style);
+                writer.block_break();
+            }
+            // Custom emojis are images, their name is in the alt text.
+            "img" => {
+                let alt = tag.attr("alt").unwrap_or("image");
+                writer.text(alt, style);
+            }
+            _ => {}
+        }
+
+        if !VOID_TAGS.contains(&&tag.name[..]) && !tag.self_closing {
+            open.push((tag.name, old_style, hidden));
+        }
+    }
+
+    while let Some((name, _, _)) = open.pop() {
+        writer.close(&name);
+    }
+    writer.block_break();
+    if writer.lines.is_empty() {
+        return None;
+    }
+    Some(writer.lines)
+}
+
+/// A tag, without the `<` and `>` around it.
+struct Tag {
+    name: String,
+    attrs: Vec<(String, String)>,
+    closing: bool,
+    self_closing: bool,
+}
+
+impl Tag {
+    fn parse(text: &str) -> Option<Self> {
+        let (closing, text) = match text.strip_prefix('/') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        let (self_closing, text) = match text.strip_suffix('/') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+
+        let name_end = text.find(|c: char| !c.is_ascii_alphanumeric()
+            && c != '-').unwrap_or(text.len());
+        if name_end == 0 {
+            return None;
+        }
+        let name = text[..name_end].to_lowercase();
+
+        let mut attrs = Vec::new();
+        let mut rest = text[name_end..].trim_start();
+        while !rest.is_empty() {
+            let key_end = rest.find(|c: char| c == '=' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let key = rest[..key_end].to_lowercase();
+            rest = rest[key_end..].trim_start();
+
+            let value = match rest.strip_prefix('=') {
+                Some(after) => {
+                    let after = after.trim_start();
+                    let (value, end) = match after.chars().next() {
+                        Some(quote) if quote == '"' || quote == '\'' => {
+                            let end = after[1..].find(quote)? + 1;
+                            (&after[1..end], end + 1)
+                        }
+                        _ => {
+                            let end = after.find(char::is_whitespace)
+                                .unwrap_or(after.len());
+                            (&after[..end], end)
+                        }
+                    };
+                    rest = &after[end..];
+                    decode(value)
+                }
+                None => String::new(),
+            };
+            attrs.push((key, value));
+            rest = rest.trim_start();
+        }
+
+        Some(Tag { name, attrs, closing, self_closing })
+    }
+
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| &value[..])
+    }
+}
+
+/// Lines written so far and the state of the blocks they are in.
+struct Writer {
+    lines: Vec<Spans<'static>>,
+    line: Vec<Span<'static>>,
+    /// Style of the text outside any tag, used for the line prefixes.
+    base: Style,
+    /// The prefix of the current line was written.
+    started: bool,
+    /// The current line has text apart from the prefix.
+    has_text: bool,
+    /// Depth of block quotes.
+    quotes: usize,
+    /// Open lists, with the next number for ordered ones.
+    lists: Vec<Option<u64>>,
+    /// Bullet of the list item starting in the current line.
+    bullet: Option<String>,
+    /// Depth of preformatted blocks, whitespace is kept inside them.
+    pre: usize,
+    /// Open links, with their address and text.
+    links: Vec<(String, String)>,
+}
+
+impl Writer {
+    fn new(base: Style) -> Self {
+        Writer {
+            lines: Vec::new(),
+            line: Vec::new(),
+            base,
+            started: false,
+            has_text: false,
+            quotes: 0,
+            lists: Vec::new(),
+            bullet: None,
+            pre: 0,
+            links: Vec::new(),
+        }
+    }
+
+    /// Writes the quote marks and list indentation of a new line.
+    fn start_line(&mut self) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        let dim = self.base.add_modifier(Modifier::DIM);
+        for _ in 0..self.quotes {
+            self.line.push(Span::styled("│ ", dim));
+        }
+        if self.lists.len() > 1 {
+            self.line.push(Span::raw("  ".repeat(self.lists.len() - 1)));
+        }
+        match self.bullet.take() {
+            Some(bullet) => self.line.push(Span::styled(bullet, self.base)),
+            None if !self.lists.is_empty() => self.line.push(Span::raw("  ")),
+            None => {}
+        }
+    }
+
+    /// Ends the current line, even if it is empty.
+    fn new_line(&mut self) {
+        self.start_line();
+        self.lines.push(Spans::from(std::mem::take(&mut self.line)));
+        self.started = false;
+        self.has_text = false;
+    }
+
+    /// Ends the current line if it has text, for the start and end of
+    /// blocks.
+    fn block_break(&mut self) {
+        if self.has_text {
+            self.new_line();
+        } else {
+            self.line.clear();
+            self.started = false;
+        }
+    }
+
+    fn text(&mut self, text: &str, style: Style) {
+        if self.pre > 0 {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    self.new_line();
+                }
+                self.push(line, style);
+            }
+            return;
+        }
+
+        // Whitespace is collapsed like in a browser, and dropped at the start
+        // of lines.
+        let mut collapsed = String::new();
+        let mut after_space = !self.has_text || self.line.last()
+            .is_none_or(|span| span.content.ends_with(' '));
+        for c in text.chars() {
+            if !c.is_whitespace() {
+                collapsed.push(c);
+                after_space = false;
+            } else if !after_space {
+                collapsed.push(' ');
+                after_space = true;
+            }
+        }
+        self.push(&collapsed, style);
+    }
+
+    fn push(&mut self, text: &str, style: Style) {
+        if text.is_empty() {
+            return;
+        }
+        self.start_line();
+        self.has_text = true;
+        if let Some((_, link_text)) = self.links.last_mut() {
+            link_text.push_str(text);
+        }
+        self.line.push(Span::styled(text.to_string(), style));
+    }
+
+    /// Ends the block started by a tag.
+    fn close(&mut self, name: &str) {
+        match name {
+            "pre" => {
+                self.block_break();
+                self.pre = self.pre.saturating_sub(1);
+            }
+            "blockquote" => {
+                self.block_break();
+                self.quotes = self.quotes.saturating_sub(1);
+            }
+            "ul" | "ol" => {
+                self.block_break();
+                self.lists.pop();
+            }
+            "p" | "div" | "table" | "tr" | "li" | "h1" | "h2" | "h3" | "h4"
+                | "h5" | "h6" => self.block_break(),
+            // The address is shown unless it is the text of the link or a
+            // mention.
+            "a" => {
+                if let Some((href, text)) = self.links.pop() {
+                    if !href.is_empty() && href != text.trim()
+                        && !href.starts_with("https://matrix.to/") {
+                        let dim = self.base.add_modifier(Modifier::DIM);
+                        self.line.push(Span::styled(
+                            [" <", &href, ">"].join(""), dim));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Replaces the character references in HTML text.
+fn decode(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) if end < 12 => end,
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let c = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            entity => entity.strip_prefix('#').and_then(|n| {
+                match n.strip_prefix(|c| c == 'x' || c == 'X') {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => n.parse().ok(),
+                }
+            }).and_then(std::char::from_u32),
+        };
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Parses a `#rrggbb` colour.
+fn parse_color(text: &str) -> Option<Color> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    Some(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
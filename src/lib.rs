@@ -28,5 +28,29 @@ pub mod tui;
 /// Event handler.
 pub mod handler;
 
+/// Command line commands.
+pub mod command;
+
+/// Emoji picker for reactions.
+pub mod emoji;
+
+/// Markdown formatting of sent messages.
+pub mod markdown;
+
+/// Formatted messages shown as styled text.
+pub mod html;
+
+/// Message text split in lines.
+pub mod text;
+
 /// Client
 pub mod client;
+
+/// Stored login sessions.
+pub mod session;
+
+/// User configuration.
+pub mod config;
+
+/// Sync response handling.
+pub mod sync;
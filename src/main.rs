@@ -16,15 +16,15 @@
  * this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::io;
+use std::{io, process};
 use tui::backend::CrosstermBackend;
 use tui::Terminal;
 use determinant:: {
     app::{App, AppResult},
+    config::Config,
     event::{Event, EventHandler},
     handler::handle_key_events,
     tui::Tui,
-    client::{login, sync},
 };
 
 fn main() -> AppResult<()> {
@@ -36,26 +36,42 @@ fn main() -> AppResult<()> {
               welcome to redistribute it under certain conditions; type \"show \
               c\" for details.");
 
+    // Read the configuration before touching the terminal so errors can be
+    // printed normally.
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("determinant: {}", e);
+            process::exit(1);
+        }
+    };
+    let tick_rate = config.tick_rate;
+
     // Create an application.
-    let mut app = App::new();
+    let mut app = App::new(config);
 
     // Initialize the terminal user interface.
     let backend = CrosstermBackend::new(io::stderr());
     let terminal = Terminal::new(backend)?;
-    let events = EventHandler::new(250);
+    let events = EventHandler::new(tick_rate);
     let mut tui = Tui::new(terminal, events);
     tui.init()?;
 
-    // Get user token from server.
-    // TODO Store preevious tokens.
-    // TODO Ask for credentials.
-    let token = login(&app.holder.server, "YOUR-USERNAME", "YOUR-PASSWORD");
-    let token = token["access_token"].to_string();
-    app.holder.token = token;
-    sync(&mut app.holder);
+    // Reuse the previous session, the login screen is shown otherwise.
+    app.resume_session();
 
     // Start the main loop.
     while app.running {
+        // Sync in the background once logged in, with the current presence.
+        tui.events.set_presence(app.presence);
+        if app.needs_sync {
+            app.prepare_sync();
+            tui.events.start_sync(app.holder.server.clone(),
+                app.holder.token.clone(), app.holder.next_batch.clone(),
+                app.holder.filter_id.clone());
+            app.needs_sync = false;
+        }
+
         // Render the user interface.
         tui.draw(&mut app)?;
 
@@ -64,8 +80,14 @@ fn main() -> AppResult<()> {
             Event::Tick => app.tick(),
             Event::Key(key_event) => handle_key_events(key_event, &mut app)?,
             Event::Mouse(_) => { },
+            // Messages are wrapped again to the new size when drawing.
             Event::Resize(_, _) => { },
+            Event::Sync(res) => app.handle_sync(res),
         }
+
+        // Keep the read markers and typing notices up to date.
+        app.mark_read();
+        app.update_typing();
     }
 
     // Exit the user interface.
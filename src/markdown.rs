@@ -0,0 +1,108 @@
+/* 
+ * Determinant: a matrix CLI client inspired by VIM.
+ * Copyright (C) 2021  Sergio Miguéns Iglesias <sergio@lony.xyz>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+
+/// URL schemes links can use in a formatted message.
+const LINK_SCHEMES: &[&str] = &["https:", "http:", "ftp:", "mailto:",
+    "magnet:"];
+
+/// Converts a message written in CommonMark to the HTML sent as its
+/// `formatted_body`. Returns `None` if the message has no formatting, so it
+/// can be sent as plain text.
+///
+/// Raw HTML in the message is escaped and links are only kept if they use a
+/// known scheme, so the result is safe to show by other clients.
+pub fn to_html(text: &str) -> Option<String> {
+    let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES;
+    let mut formatted = false;
+    // Whether each open link or image was kept.
+    let mut links: Vec<bool> = Vec::new();
+
+    let events: Vec<Event> = Parser::new_ext(text, options)
+        .filter_map(|event| {
+            match &event {
+                Event::Start(Tag::Paragraph) | Event::End(Tag::Paragraph)
+                    | Event::Text(_) | Event::Html(_) | Event::SoftBreak
+                    | Event::HardBreak => {}
+                _ => formatted = true,
+            }
+            match event {
+                Event::Html(html) => Some(Event::Text(html)),
+                Event::Start(Tag::Link(kind, url, title)) => {
+                    let safe = is_safe_url(&url);
+                    links.push(safe);
+                    if safe {
+                        Some(Event::Start(Tag::Link(kind, url, title)))
+                    } else {
+                        None
+                    }
+                }
+                // Only images in the homeserver can be shown, the rest are
+                // sent as links.
+                Event::Start(Tag::Image(kind, url, title)) => {
+                    let tag = if url.starts_with("mxc://") {
+                        Some(Tag::Image(kind, url, title))
+                    } else if is_safe_url(&url) {
+                        Some(Tag::Link(kind, url, title))
+                    } else {
+                        None
+                    };
+                    links.push(tag.is_some());
+                    tag.map(Event::Start)
+                }
+                Event::End(Tag::Link(kind, url, title)) => {
+                    match links.pop() {
+                        Some(true) => Some(Event::End(Tag::Link(kind, url,
+                            title))),
+                        _ => None,
+                    }
+                }
+                Event::End(Tag::Image(kind, url, title)) => {
+                    match links.pop() {
+                        Some(true) if url.starts_with("mxc://") => {
+                            Some(Event::End(Tag::Image(kind, url, title)))
+                        }
+                        Some(true) => Some(Event::End(Tag::Link(kind, url,
+                            title))),
+                        _ => None,
+                    }
+                }
+                event => Some(event),
+            }
+        })
+        .collect();
+
+    if !formatted {
+        return None;
+    }
+    let mut html = String::new();
+    html::push_html(&mut html, events.into_iter());
+    let html = html.trim_end();
+
+    // A single paragraph is sent without the tags around it.
+    match html.strip_prefix("<p>").and_then(|html| html.strip_suffix("</p>")) {
+        Some(inner) if !inner.contains("<p>") => Some(inner.to_string()),
+        _ => Some(html.to_string()),
+    }
+}
+
+fn is_safe_url(url: &str) -> bool {
+    let url = url.to_lowercase();
+    LINK_SCHEMES.iter().any(|scheme| url.starts_with(scheme))
+}
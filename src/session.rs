@@ -0,0 +1,130 @@
+/* 
+ * Determinant: a matrix CLI client inspired by VIM.
+ * Copyright (C) 2021  Sergio Miguéns Iglesias <sergio@lony.xyz>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std:: {
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use crate::client::{sync_filter, DataHolder};
+
+/// Login data kept between runs so the user does not have to log in again.
+///
+/// The sync token is not kept: the rooms are not stored, so the first sync of
+/// each run has to send the whole state and the latest messages again.
+pub struct Session {
+    pub homeserver: String,
+    pub user_id: String,
+    pub access_token: String,
+    pub device_id: String,
+
+    /// ID of the uploaded sync filter.
+    pub filter_id: String,
+    /// Definition of the filter, if it changes the filter is uploaded again.
+    pub filter: String,
+}
+
+impl Session {
+    /// Takes the session data from the client state.
+    pub fn from_holder(holder: &DataHolder) -> Self {
+        Self {
+            homeserver: holder.server.address.clone(),
+            user_id: holder.user_id.clone(),
+            access_token: holder.token.clone(),
+            device_id: holder.device_id.clone(),
+            filter_id: holder.filter_id.clone(),
+            filter: sync_filter().dump(),
+        }
+    }
+
+    /// Copies the session data into the client state.
+    pub fn restore(&self, holder: &mut DataHolder) {
+        holder.server.address = self.homeserver.clone();
+        holder.user_id = self.user_id.clone();
+        holder.token = self.access_token.clone();
+        holder.device_id = self.device_id.clone();
+        if self.filter == sync_filter().dump() {
+            holder.filter_id = self.filter_id.clone();
+        }
+    }
+
+    /// Path of the session file, inside the XDG data directory.
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("determinant").join("session"))
+    }
+
+    /// Reads the stored session, if there is a valid one.
+    pub fn load() -> Option<Self> {
+        let data = fs::read_to_string(Self::path()?).ok()?;
+        let data = json::parse(&data).ok()?;
+
+        let session = Self {
+            homeserver: data["homeserver"].as_str()?.to_string(),
+            user_id: data["user_id"].as_str()?.to_string(),
+            access_token: data["access_token"].as_str()?.to_string(),
+            device_id: data["device_id"].as_str().unwrap_or("").to_string(),
+            filter_id: data["filter_id"].as_str().unwrap_or("").to_string(),
+            filter: data["filter"].as_str().unwrap_or("").to_string(),
+        };
+
+        if session.homeserver.is_empty() || session.access_token.is_empty() {
+            return None;
+        }
+        Some(session)
+    }
+
+    /// Writes the session to disk. The file is only readable by the user as
+    /// it contains the access token.
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path().ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound, "no data directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let data = json::object! {
+            "homeserver": &self.homeserver[..],
+            "user_id": &self.user_id[..],
+            "access_token": &self.access_token[..],
+            "device_id": &self.device_id[..],
+            "filter_id": &self.filter_id[..],
+            "filter": &self.filter[..],
+        };
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&path)?;
+        // The mode is only applied on creation, fix older files too.
+        #[cfg(unix)]
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(json::stringify_pretty(data, 4).as_bytes())
+    }
+
+    /// Deletes the stored session.
+    pub fn remove() -> io::Result<()> {
+        match Self::path() {
+            Some(path) if path.exists() => fs::remove_file(path),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,492 @@
+/* 
+ * Determinant: a matrix CLI client inspired by VIM.
+ * Copyright (C) 2021  Sergio Miguéns Iglesias <sergio@lony.xyz>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::cmp::Reverse;
+use json::JsonValue;
+use crate::client::{DataHolder, Message, MessageContent, Presence, Reaction,
+    RoomData, StateChange, UserData};
+
+/// Applies a sync response to the client data.
+///
+/// Applying the same response more than once leaves the data as it was after
+/// the first time, so overlapping responses from different sync requests do
+/// not duplicate anything.
+pub fn apply_sync(holder: &mut DataHolder, res: &JsonValue) {
+    for (room_id, room) in res["rooms"]["join"].entries() {
+        apply_joined_room(holder, room_id, room);
+    }
+
+    for (room_id, room) in res["rooms"]["invite"].entries() {
+        apply_invited_room(holder, room_id, room);
+    }
+
+    for (room_id, _) in res["rooms"]["leave"].entries() {
+        apply_left_room(holder, room_id);
+    }
+
+    for event in res["account_data"]["events"].members() {
+        holder.account_data.insert(event["type"].to_string(),
+            event["content"].clone());
+    }
+
+    for event in res["presence"]["events"].members() {
+        apply_presence_event(holder, event);
+    }
+
+    if res["next_batch"].is_string() {
+        holder.next_batch = res["next_batch"].to_string();
+    }
+}
+
+/// Adds the older messages returned by a `/messages` request before the ones
+/// already in the room.
+pub fn apply_messages(holder: &mut DataHolder, room_id: &str,
+    res: &JsonValue) {
+    // Lazy loaded members, only used for the names of the senders.
+    for event in res["state"].members() {
+        let user_id = event["state_key"].to_string();
+        if event["type"] == "m.room.member"
+            && !holder.users.contains_key(&user_id) {
+            holder.users.insert(user_id.clone(), UserData {
+                name: event["content"]["displayname"].as_str()
+                    .unwrap_or(&user_id).to_string(),
+                ..UserData::default()
+            });
+        }
+    }
+
+    let room_data = match holder.rooms.get_mut(room_id) {
+        Some(room_data) => room_data,
+        None => return,
+    };
+
+    // The events come newest first.
+    let mut older = RoomData::default();
+    for event in res["chunk"].members().rev() {
+        let event_id = event["event_id"].as_str().unwrap_or("");
+        if !room_data.messages.iter().any(|msg| msg.event_id == event_id) {
+            apply_timeline_event(&mut older, room_id, event);
+        }
+    }
+    older.messages.append(&mut room_data.messages);
+    room_data.messages = older.messages;
+    room_data.pending_relations.append(&mut older.pending_relations);
+    apply_pending_relations(room_data, room_id);
+
+    // There is no end token once the start of the room is reached.
+    room_data.prev_batch = res["end"].as_str().unwrap_or("").to_string();
+    if res["chunk"].is_empty() {
+        room_data.prev_batch = String::new();
+    }
+}
+
+/// Applies the changes of a room the user is in.
+fn apply_joined_room(holder: &mut DataHolder, room_id: &str,
+    room: &JsonValue) {
+    holder.room_invites.retain(|id| id != room_id);
+    holder.invited_rooms.remove(room_id);
+    let mut room_data = holder.rooms.remove(room_id).unwrap_or_default();
+
+    // The summary only includes the fields that changed.
+    let summary = &room["summary"];
+    if summary["m.heroes"].is_array() {
+        room_data.heroes = summary["m.heroes"].members()
+            .map(|user_id| user_id.to_string())
+            .collect();
+    }
+    if let Some(count) = summary["m.joined_member_count"].as_u64() {
+        room_data.joined_count = count;
+    }
+    if let Some(count) = summary["m.invited_member_count"].as_u64() {
+        room_data.invited_count = count;
+    }
+
+    // State before the timeline.
+    for event in room["state"]["events"].members() {
+        apply_state_event(holder, &mut room_data, room_id, event);
+    }
+
+    /*
+     * A limited timeline means some events were skipped, the old messages
+     * are dropped so there is no gap between them and the new ones. They can
+     * be fetched again with the `prev_batch` token.
+     */
+    let timeline = &room["timeline"];
+    if timeline["limited"] == true {
+        room_data.messages.clear();
+    }
+    if room_data.messages.is_empty() && timeline["prev_batch"].is_string() {
+        room_data.prev_batch = timeline["prev_batch"].to_string();
+    }
+
+    for event in timeline["events"].members() {
+        if let Some(ts) = event["origin_server_ts"].as_u64() {
+            room_data.last_activity = room_data.last_activity.max(ts);
+        }
+        if event["state_key"].is_string() {
+            apply_state_event(holder, &mut room_data, room_id, event);
+        }
+        apply_timeline_event(&mut room_data, room_id, event);
+    }
+
+    for event in room["ephemeral"]["events"].members() {
+        apply_ephemeral_event(&mut room_data, event);
+    }
+
+    for event in room["account_data"]["events"].members() {
+        room_data.account_data.insert(event["type"].to_string(),
+            event["content"].clone());
+    }
+
+    let members_changed = room["state"]["events"].members()
+        .chain(timeline["events"].members())
+        .any(|event| event["type"] == "m.room.member");
+    if members_changed {
+        room_data.mention_names = mention_names(holder, &room_data.members);
+    }
+
+    let unread = &room["unread_notifications"];
+    if let Some(count) = unread["notification_count"].as_u32() {
+        room_data.unread_msgs = count;
+    }
+    if let Some(count) = unread["highlight_count"].as_u32() {
+        room_data.highlight_msgs = count;
+    }
+
+    holder.rooms.insert(room_id.to_string(), room_data);
+}
+
+/// Names the members can be mentioned by, from the longest to the shortest
+/// so the longest name is found when one is the start of another.
+fn mention_names(holder: &DataHolder, members: &[String])
+    -> Vec<(String, String)> {
+    let mut names: Vec<(String, String)> = members.iter()
+        .flat_map(|user_id| [
+            (holder.user_name(user_id), user_id.clone()),
+            (user_id.clone(), user_id.clone()),
+        ])
+        // Single letters would be found everywhere.
+        .filter(|(name, _)| name.chars().count() > 1)
+        .collect();
+    names.sort_by_key(|(name, _)| (Reverse(name.len()), name.clone()));
+    names.dedup_by(|(a, _), (b, _)| a == b);
+    names
+}
+
+/// Adds a room the user was invited to, with the part of its state shared
+/// with the invite.
+fn apply_invited_room(holder: &mut DataHolder, room_id: &str,
+    room: &JsonValue) {
+    if !holder.room_invites.iter().any(|id| id == room_id) {
+        holder.room_invites.push(room_id.to_string());
+    }
+
+    // The whole invite state is sent every time.
+    let mut room_data = RoomData::default();
+    for event in room["invite_state"]["events"].members() {
+        let content = &event["content"];
+        match event["type"].as_str().unwrap_or("") {
+            "m.room.member" => {
+                let user_id = event["state_key"].to_string();
+                if user_id == holder.user_id
+                    && content["membership"] == "invite" {
+                    room_data.inviter = event["sender"].to_string();
+                } else if content["membership"] == "join" {
+                    // Only for the names, the user is not in the room yet.
+                    if !holder.users.contains_key(&user_id) {
+                        holder.users.insert(user_id.clone(), UserData {
+                            name: content["displayname"].as_str()
+                                .unwrap_or(&user_id).to_string(),
+                            ..UserData::default()
+                        });
+                    }
+                    if !room_data.members.contains(&user_id) {
+                        room_data.members.push(user_id);
+                    }
+                }
+            }
+
+            "m.room.name" => {
+                room_data.name = content["name"].as_str().unwrap_or("")
+                    .to_string();
+            }
+
+            "m.room.canonical_alias" => {
+                room_data.alias = content["alias"].as_str().unwrap_or("")
+                    .to_string();
+            }
+
+            "m.room.topic" => {
+                room_data.topic = content["topic"].as_str().unwrap_or("")
+                    .to_string();
+            }
+
+            _ => {}
+        }
+    }
+    // There is no summary, the user's own invite counts as a member so a
+    // direct chat is named after the inviter.
+    room_data.joined_count = room_data.members.len() as u64;
+    room_data.invited_count = 1;
+    holder.invited_rooms.insert(room_id.to_string(), room_data);
+}
+
+/// Forgets a room the user left, was kicked from or whose invite was
+/// rejected or rescinded.
+fn apply_left_room(holder: &mut DataHolder, room_id: &str) {
+    holder.room_invites.retain(|id| id != room_id);
+    holder.invited_rooms.remove(room_id);
+    holder.rooms.remove(room_id);
+    for user in holder.users.values_mut() {
+        user.rooms.retain(|id| id != room_id);
+    }
+}
+
+/// Updates the room with a state event.
+fn apply_state_event(holder: &mut DataHolder, room_data: &mut RoomData,
+    room_id: &str, event: &JsonValue) {
+    let content = &event["content"];
+
+    match event["type"].as_str().unwrap_or("") {
+        "m.room.member" => {
+            let user_id = event["state_key"].to_string();
+            if content["membership"] == "join" {
+                let name = content["displayname"].as_str()
+                    .unwrap_or(&user_id).to_string();
+                let user = holder.users.entry(user_id.clone())
+                    .or_default();
+                user.name = name;
+                if !user.rooms.iter().any(|id| id == room_id) {
+                    user.rooms.push(room_id.to_string());
+                }
+
+                if !room_data.members.contains(&user_id) {
+                    room_data.members.push(user_id);
+                }
+            } else {
+                room_data.members.retain(|id| id != &user_id);
+                if let Some(user) = holder.users.get_mut(&user_id) {
+                    user.rooms.retain(|id| id != room_id);
+                }
+            }
+        }
+
+        "m.room.name" => {
+            room_data.name = content["name"].as_str().unwrap_or("")
+                .to_string();
+        }
+
+        "m.room.canonical_alias" => {
+            room_data.alias = content["alias"].as_str().unwrap_or("")
+                .to_string();
+        }
+
+        "m.room.topic" => {
+            room_data.topic = content["topic"].as_str().unwrap_or("")
+                .to_string();
+        }
+
+        _ => {}
+    }
+}
+
+/// Adds a timeline event to the room if it was not already there. Edits and
+/// redactions change the messages they refer to instead.
+fn apply_timeline_event(room_data: &mut RoomData, room_id: &str,
+    event: &JsonValue) {
+    let event_content = &event["content"];
+    let relation = &event_content["m.relates_to"];
+
+    if event["type"] == "m.room.redaction" {
+        // Newer room versions have it in the content.
+        let redacts = event["redacts"].as_str()
+            .or_else(|| event_content["redacts"].as_str())
+            .unwrap_or("");
+        if let Some(msg) = room_data.messages.iter_mut()
+            .find(|msg| msg.event_id == redacts) {
+            msg.content = MessageContent::Redacted;
+            msg.html = String::new();
+            msg.edited = false;
+            msg.reactions.clear();
+        }
+        for msg in room_data.messages.iter_mut() {
+            msg.reactions.retain(|reaction| reaction.event_id != redacts);
+        }
+        room_data.pending_relations
+            .retain(|event| event["event_id"] != redacts);
+        return;
+    }
+
+    if event["type"] == "m.reaction" {
+        apply_reaction(room_data, event);
+        return;
+    }
+
+    // Only the sender can edit a message.
+    if relation["rel_type"] == "m.replace" {
+        let target = relation["event_id"].as_str().unwrap_or("");
+        match room_data.messages.iter_mut()
+            .find(|msg| msg.event_id == target) {
+            Some(msg) if event["sender"].as_str() == Some(&msg.sender)
+                && msg.content != MessageContent::Redacted => {
+                let new_content = &event_content["m.new_content"];
+                msg.content = MessageContent::parse(new_content);
+                msg.html = MessageContent::formatted_body(new_content);
+                msg.edited = true;
+            }
+            Some(_) => {}
+            None => keep_pending(room_data, event),
+        }
+        return;
+    }
+
+    let content = if event["state_key"].is_string() {
+        match StateChange::parse(event) {
+            Some(change) => MessageContent::State(change),
+            None => return,
+        }
+    } else if event["type"] == "m.room.message"
+        && event["unsigned"]["redacted_because"].is_object() {
+        MessageContent::Redacted
+    } else if event["type"] == "m.room.message"
+        && event_content["msgtype"].is_string() {
+        MessageContent::parse(event_content)
+    } else {
+        return;
+    };
+
+    let event_id = event["event_id"].to_string();
+    if room_data.messages.iter().any(|msg| msg.event_id == event_id) {
+        return;
+    }
+
+    let reply_to = relation["m.in_reply_to"]["event_id"].as_str()
+        .unwrap_or("").to_string();
+    let content = if reply_to.is_empty() {
+        content
+    } else {
+        content.strip_reply_fallback()
+    };
+
+    room_data.messages.push(Message {
+        event_id,
+        html: MessageContent::formatted_body(event_content),
+        content,
+        sender: event["sender"].to_string(),
+        room: room_id.to_string(),
+        timestamp: event["origin_server_ts"].as_u64().unwrap_or(0),
+        reply_to,
+        edited: false,
+        reactions: Vec::new(),
+    });
+}
+
+/// Keeps a relation to a message that is not loaded, so it can be applied
+/// when older messages are loaded.
+fn keep_pending(room_data: &mut RoomData, event: &JsonValue) {
+    if !room_data.pending_relations.iter()
+        .any(|pending| pending["event_id"] == event["event_id"]) {
+        room_data.pending_relations.push(event.clone());
+    }
+}
+
+/// Applies the relations kept for messages that were not loaded. The ones
+/// whose message is still missing are kept again.
+fn apply_pending_relations(room_data: &mut RoomData, room_id: &str) {
+    let mut pending = std::mem::take(&mut room_data.pending_relations);
+    // Relations can come in any order when paginating, edits have to be
+    // applied from the oldest to the newest.
+    pending.sort_by_key(|event| event["origin_server_ts"].as_u64()
+        .unwrap_or(0));
+    for event in &pending {
+        apply_timeline_event(room_data, room_id, event);
+    }
+}
+
+/// Adds a reaction to the message it annotates. Reactions to messages that
+/// are not loaded are kept until they are.
+fn apply_reaction(room_data: &mut RoomData, event: &JsonValue) {
+    let relation = &event["content"]["m.relates_to"];
+    if relation["rel_type"] != "m.annotation"
+        || event["unsigned"]["redacted_because"].is_object() {
+        return;
+    }
+    let target = relation["event_id"].as_str().unwrap_or("");
+    let msg = match room_data.messages.iter_mut()
+        .find(|msg| msg.event_id == target) {
+        Some(msg) => msg,
+        None => return keep_pending(room_data, event),
+    };
+
+    let reaction = Reaction {
+        event_id: event["event_id"].to_string(),
+        sender: event["sender"].to_string(),
+        key: relation["key"].to_string(),
+    };
+    // A user can only react once with each key.
+    if !msg.reactions.iter().any(|r| r.event_id == reaction.event_id
+        || (r.sender == reaction.sender && r.key == reaction.key)) {
+        msg.reactions.push(reaction);
+    }
+}
+
+/// Updates the presence of a user.
+fn apply_presence_event(holder: &mut DataHolder, event: &JsonValue) {
+    let content = &event["content"];
+    let presence = match content["presence"].as_str()
+        .and_then(Presence::parse) {
+        Some(presence) => presence,
+        None => return,
+    };
+
+    let user_id = event["sender"].to_string();
+    let user = holder.users.entry(user_id.clone()).or_insert_with(|| {
+        UserData {
+            name: content["displayname"].as_str().unwrap_or(&user_id)
+                .to_string(),
+            ..UserData::default()
+        }
+    });
+    user.presence = presence;
+    user.last_active_ago = content["last_active_ago"].as_u64();
+    user.status_msg = content["status_msg"].as_str().unwrap_or("")
+        .to_string();
+}
+
+/// Updates the room with an ephemeral event.
+fn apply_ephemeral_event(room_data: &mut RoomData, event: &JsonValue) {
+    match event["type"].as_str().unwrap_or("") {
+        "m.typing" => {
+            room_data.typing = event["content"]["user_ids"].members()
+                .map(|user_id| user_id.to_string())
+                .collect();
+        }
+
+        // Receipts only include the changes, newer ones replace older ones.
+        "m.receipt" => {
+            for (event_id, receipts) in event["content"].entries() {
+                for (user_id, _) in receipts["m.read"].entries() {
+                    room_data.receipts.insert(user_id.to_string(),
+                        event_id.to_string());
+                }
+            }
+        }
+
+        _ => {}
+    }
+}
@@ -0,0 +1,183 @@
+/* 
+ * Determinant: a matrix CLI client inspired by VIM.
+ * Copyright (C) 2021  Sergio Miguéns Iglesias <sergio@lony.xyz>
+ *
+ * This program is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+ * FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+ * details.
+
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeZone, Utc};
+use tui::{
+    style::Style,
+    text::{Span, Spans},
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use crate::config::ColorConfig;
+
+/// Splits a line in lines of at most `width` columns, between words when
+/// possible. Words longer than a line are split anywhere.
+pub fn wrap(line: Spans<'static>, width: usize) -> Vec<Spans<'static>> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut current: Vec<Span<'static>> = Vec::new();
+    let mut current_w = 0;
+
+    for span in line.0 {
+        for word in split_words(&span.content) {
+            let word_w = word.width();
+            if current_w + word_w <= width {
+                push_text(&mut current, word, span.style);
+                current_w += word_w;
+            } else if word.starts_with(char::is_whitespace) {
+                // Spaces where the line breaks are dropped.
+                end_line(&mut lines, &mut current);
+                current_w = 0;
+            } else if word_w <= width {
+                end_line(&mut lines, &mut current);
+                push_text(&mut current, word, span.style);
+                current_w = word_w;
+            } else {
+                for c in word.chars() {
+                    let c_w = c.width().unwrap_or(0);
+                    if current_w + c_w > width && current_w > 0 {
+                        end_line(&mut lines, &mut current);
+                        current_w = 0;
+                    }
+                    push_text(&mut current, &c.to_string(), span.style);
+                    current_w += c_w;
+                }
+            }
+        }
+    }
+    lines.push(Spans::from(current));
+    lines
+}
+
+/// Adds a line where the text breaks, without the spaces at its end.
+fn end_line(lines: &mut Vec<Spans<'static>>, line: &mut Vec<Span<'static>>) {
+    while let Some(last) = line.last_mut() {
+        let trimmed = last.content.trim_end();
+        if !trimmed.is_empty() {
+            if trimmed.len() < last.content.len() {
+                last.content = trimmed.to_string().into();
+            }
+            break;
+        }
+        line.pop();
+    }
+    lines.push(Spans::from(std::mem::take(line)));
+}
+
+/// Splits text in words and the whitespace between them.
+pub fn split_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut blank = None;
+    for (i, c) in text.char_indices() {
+        let is_blank = c.is_whitespace();
+        if blank.is_some() && blank != Some(is_blank) {
+            words.push(&text[start..i]);
+            start = i;
+        }
+        blank = Some(is_blank);
+    }
+    if start < text.len() {
+        words.push(&text[start..]);
+    }
+    words
+}
+
+/// Adds text at the end of a line, to the last span if it has the same
+/// style.
+fn push_text(line: &mut Vec<Span<'static>>, text: &str, style: Style) {
+    match line.last_mut() {
+        Some(last) if last.style == style => {
+            last.content = [&last.content, text].join("").into();
+        }
+        _ => line.push(Span::styled(text.to_string(), style)),
+    }
+}
+
+/// Colours the names of the users mentioned in a line with their nick
+/// colours. `names` are the names and the users they belong to, from the
+/// longest to the shortest, so the longest name found at a position is used.
+pub fn color_mentions(line: Spans<'static>, names: &[(String, String)],
+    colors: &ColorConfig) -> Spans<'static> {
+    let mut spans = Vec::new();
+    for span in line.0 {
+        let content = span.content.to_string();
+        let mut rest = &content[..];
+        while let Some((start, name, user_id)) = find_mention(rest, names) {
+            if start > 0 {
+                spans.push(Span::styled(rest[..start].to_string(),
+                    span.style));
+            }
+            let color = colors.nick_color(user_id);
+            spans.push(Span::styled(name.to_string(), span.style.fg(color)));
+            rest = &rest[start + name.len()..];
+        }
+        if !rest.is_empty() {
+            spans.push(Span::styled(rest.to_string(), span.style));
+        }
+    }
+    Spans::from(spans)
+}
+
+/// First name in a text that is not part of a longer word, with the user it
+/// belongs to.
+fn find_mention<'a>(text: &str, names: &'a [(String, String)])
+    -> Option<(usize, &'a str, &'a str)> {
+    let mut found: Option<(usize, &str, &str)> = None;
+    for (name, user_id) in names {
+        let start = text.match_indices(&name[..]).map(|(i, _)| i)
+            .find(|i| {
+                let before = text[..*i].chars().next_back();
+                let after = text[i + name.len()..].chars().next();
+                !before.is_some_and(char::is_alphanumeric)
+                    && !after.is_some_and(char::is_alphanumeric)
+            });
+        match (start, found) {
+            (Some(start), Some((i, _, _))) if start >= i => {}
+            (Some(start), _) => found = Some((start, name, user_id)),
+            (None, _) => {}
+        }
+    }
+    found
+}
+
+/// Time of a timestamp in milliseconds, in the local time zone or in UTC.
+/// Messages without a timestamp have none.
+pub fn message_time(timestamp: u64, utc: bool)
+    -> Option<DateTime<FixedOffset>> {
+    if timestamp == 0 {
+        return None;
+    }
+    let time = Utc.timestamp_millis_opt(timestamp as i64).single()?;
+    if utc {
+        Some(time.into())
+    } else {
+        Some(time.with_timezone(&Local).into())
+    }
+}
+
+/// Separator shown before the first message of each day, with the day in
+/// `format`. `last_day` is the day of the message before, it is updated.
+pub fn day_separator(time: &DateTime<FixedOffset>,
+    last_day: &mut Option<NaiveDate>, format: &str) -> Option<String> {
+    let day = time.date_naive();
+    if *last_day == Some(day) {
+        return None;
+    }
+    *last_day = Some(day);
+    Some(["── ", &time.format(format).to_string(), " ──"].join(""))
+}
@@ -0,0 +1,106 @@
+use determinant::client::{DataHolder, Server};
+use determinant::command::CommandLine;
+
+fn holder() -> DataHolder {
+    DataHolder::new(Server {
+        address: String::from("https://example.org"),
+    })
+}
+
+/// Types text at the end of the command line.
+fn type_text(line: &mut CommandLine, text: &str) {
+    for c in text.chars() {
+        line.push(c);
+    }
+}
+
+/// Command line with the text typed in it.
+fn typed(text: &str) -> CommandLine {
+    let mut line = CommandLine::default();
+    type_text(&mut line, text);
+    line
+}
+
+#[test]
+fn completions_are_cycled() {
+    let holder = holder();
+    let mut line = typed("q");
+    line.complete(&holder);
+    assert_eq!(line.text, "q");
+    line.complete(&holder);
+    assert_eq!(line.text, "quit");
+    line.complete(&holder);
+    assert_eq!(line.text, "q");
+
+    // Typing starts a new completion.
+    line.push('u');
+    line.complete(&holder);
+    assert_eq!(line.text, "quit");
+
+    let mut line = typed("xyz");
+    line.complete(&holder);
+    assert_eq!(line.text, "xyz");
+}
+
+#[test]
+fn presence_states_are_completed() {
+    let holder = holder();
+    let mut line = typed("presence ");
+    line.complete(&holder);
+    assert_eq!(line.text, "presence offline");
+    line.complete(&holder);
+    assert_eq!(line.text, "presence online");
+    line.complete(&holder);
+    assert_eq!(line.text, "presence unavailable");
+
+    let mut line = typed("presence u");
+    line.complete(&holder);
+    assert_eq!(line.text, "presence unavailable");
+
+    // The status message is not completed.
+    let mut line = typed("presence online o");
+    line.complete(&holder);
+    assert_eq!(line.text, "presence online o");
+}
+
+#[test]
+fn history_gives_the_draft_back() {
+    let mut line = typed("join #a:example.org");
+    assert_eq!(line.take(), "join #a:example.org");
+    type_text(&mut line, "sync");
+    assert_eq!(line.take(), "sync");
+
+    type_text(&mut line, "nick");
+    line.history_prev();
+    assert_eq!(line.text, "sync");
+    line.history_prev();
+    assert_eq!(line.text, "join #a:example.org");
+    // There is nothing older.
+    line.history_prev();
+    assert_eq!(line.text, "join #a:example.org");
+    line.history_next();
+    assert_eq!(line.text, "sync");
+    line.history_next();
+    assert_eq!(line.text, "nick");
+    line.history_next();
+    assert_eq!(line.text, "nick");
+}
+
+#[test]
+fn repeated_commands_are_kept_once() {
+    let mut line = CommandLine::default();
+    for text in ["sync", " sync ", "vsplit", "sync", ""] {
+        line.text = text.to_string();
+        line.take();
+    }
+    line.history_prev();
+    assert_eq!(line.text, "sync");
+    line.history_prev();
+    assert_eq!(line.text, "vsplit");
+    line.history_prev();
+    assert_eq!(line.text, "sync");
+    line.history_prev();
+    assert_eq!(line.text, "sync");
+    line.history_next();
+    assert_eq!(line.text, "vsplit");
+}
@@ -0,0 +1,68 @@
+use determinant::config::{Config, ConfigError};
+use tui::style::Color;
+
+/// Option and reason of the error given for a configuration.
+fn error(text: &str) -> (String, String) {
+    match Config::parse(text) {
+        Err(ConfigError::Invalid(_, key, reason)) => (key, reason),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("the configuration was accepted"),
+    }
+}
+
+#[test]
+fn options_are_read() {
+    let config = Config::parse("[interface]\ntick_rate = 100\n\
+        [colors]\ntext = \"#102030\"\nnicks = [\"red\", 4]\n\
+        [keys]\nreply = \"R\"\n").unwrap();
+    assert_eq!(config.tick_rate, 100);
+    assert_eq!(config.colors.text, Color::Rgb(0x10, 0x20, 0x30));
+    assert_eq!(config.colors.nicks, [Color::Red, Color::Indexed(4)]);
+    assert_eq!(config.keys.reply, 'R');
+}
+
+#[test]
+fn unknown_section() {
+    assert_eq!(error("[look]\ntheme = \"dark\"\n"),
+        (String::from("look"), String::from("unknown section")));
+}
+
+#[test]
+fn value_out_of_range() {
+    assert_eq!(error("[interface]\ntick_rate = 0\n"),
+        (String::from("interface.tick_rate"),
+        String::from("expected a number between 1 and 60000")));
+}
+
+#[test]
+fn bad_colour() {
+    assert_eq!(error("[colors]\nborder = \"pink\"\n"),
+        (String::from("colors.border"),
+        String::from("unknown colour \"pink\"")));
+    assert_eq!(error("[colors]\nborder = 300\n").1,
+        "colour numbers go from 0 to 255");
+}
+
+#[test]
+fn key_longer_than_one_character() {
+    assert_eq!(error("[keys]\nquit = \"qq\"\n"),
+        (String::from("keys.quit"),
+        String::from("expected a single character")));
+}
+
+#[test]
+fn nick_colours_match_element() {
+    // Element numbers its eight colours with the same hash.
+    let config = Config::parse("[colors]\nnicks = [0, 1, 2, 3, 4, 5, 6, 7]\n")
+        .unwrap();
+    let colors = &config.colors;
+    assert_eq!(colors.nick_color("@alice:example.org"), Color::Indexed(6));
+    assert_eq!(colors.nick_color("@bob:example.org"), Color::Indexed(3));
+    // The hash of this one overflows to a negative number.
+    assert_eq!(colors.nick_color("@carol:matrix.org"), Color::Indexed(0));
+    // Characters outside the BMP are hashed as two UTF-16 units.
+    assert_eq!(colors.nick_color("@😀:example.org"), Color::Indexed(1));
+
+    let config = Config::parse("[colors]\nnicks = [\"red\"]\n").unwrap();
+    assert_eq!(config.colors.nick_color("@bob:example.org"), Color::Red);
+}
@@ -0,0 +1,66 @@
+use determinant::html::to_lines;
+use tui::style::{Color, Modifier, Style};
+use tui::text::Spans;
+
+fn text(lines: &[Spans]) -> Vec<String> {
+    lines.iter()
+        .map(|line| line.0.iter().map(|span| span.content.as_ref()).collect())
+        .collect()
+}
+
+fn lines(html: &str) -> Vec<Spans<'static>> {
+    to_lines(html, Style::default(), Color::Yellow).unwrap()
+}
+
+#[test]
+fn inline_tags_are_styled() {
+    let lines = lines("Hello <b>bold</b> and <code>x &lt; y</code>");
+    assert_eq!(text(&lines), ["Hello bold and x < y"]);
+    assert!(lines[0].0[1].style.add_modifier.contains(Modifier::BOLD));
+    assert_eq!(lines[0].0[3].style.fg, Some(Color::Yellow));
+}
+
+#[test]
+fn blocks_are_split_in_lines() {
+    let lines = lines("<p>One</p>\n<ul>\n<li>first</li>\n<li>second</li>\n\
+        </ul>\n<blockquote>\n<p>quoted</p>\n</blockquote>\n\
+        <pre><code>a\n  b\n</code></pre>\n\
+        <ol start=\"3\"><li>three</li></ol>");
+    assert_eq!(text(&lines), ["One", "• first", "• second", "│ quoted", "a",
+        "  b", "3. three"]);
+}
+
+#[test]
+fn replies_and_links() {
+    let lines = lines("<mx-reply><blockquote>old</blockquote></mx-reply>\
+        See <a href=\"https://example.org\">this</a><br>and \
+        <a href=\"https://matrix.to/#/@alice:example.org\">Alice</a>");
+    assert_eq!(text(&lines), ["See this <https://example.org>", "and Alice"]);
+}
+
+#[test]
+fn malformed_html_is_rejected() {
+    assert!(to_lines("<b>bold</i>", Style::default(), Color::Yellow)
+        .is_none());
+    assert!(to_lines("a <b", Style::default(), Color::Yellow).is_none());
+    assert!(to_lines("<p></p>", Style::default(), Color::Yellow).is_none());
+}
+
+#[test]
+fn spoilers_are_hidden() {
+    let lines = lines("The killer is <span data-mx-spoiler>the butler\
+        </span>.<br><span data-mx-spoiler=\"ending\">They <b>die</b></span>");
+    assert_eq!(text(&lines), ["The killer is [spoiler].",
+        "[spoiler: ending]"]);
+}
+
+#[test]
+fn blocks_in_spoilers_do_not_close_the_outer_ones() {
+    let quote = lines("<blockquote>q1 <span data-mx-spoiler><blockquote>x\
+        </blockquote></span><p>q2</p></blockquote>");
+    assert_eq!(text(&quote), ["│ q1 [spoiler]", "│ q2"]);
+
+    let list = lines("<ul><li>a <span data-mx-spoiler><ul><li>b</li></ul>\
+        </span> c<ul><li>nested</li></ul></li><li>d</li></ul>");
+    assert_eq!(text(&list), ["• a [spoiler] c", "  • nested", "• d"]);
+}
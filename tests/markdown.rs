@@ -0,0 +1,28 @@
+use determinant::markdown::to_html;
+
+#[test]
+fn plain_text_is_not_formatted() {
+    assert_eq!(to_html("Hello there"), None);
+    assert_eq!(to_html("a < b\nand b > c"), None);
+}
+
+#[test]
+fn markdown_is_formatted() {
+    assert_eq!(to_html("Hello **there**").unwrap(),
+        "Hello <strong>there</strong>");
+    assert_eq!(to_html("```rust\nlet x = 1;\n```").unwrap(),
+        "<pre><code class=\"language-rust\">let x = 1;\n</code></pre>");
+    assert_eq!(to_html("- one\n- two").unwrap(),
+        "<ul>\n<li>one</li>\n<li>two</li>\n</ul>");
+}
+
+#[test]
+fn html_is_sanitized() {
+    assert_eq!(to_html("*hi* <script>alert(1)</script>").unwrap(),
+        "<em>hi</em> &lt;script&gt;alert(1)&lt;/script&gt;");
+    assert_eq!(to_html("[click](javascript:alert(1))").unwrap(), "click");
+    assert_eq!(to_html("[site](https://example.org)").unwrap(),
+        "<a href=\"https://example.org\">site</a>");
+    assert_eq!(to_html("![cat](https://example.org/cat.png)").unwrap(),
+        "<a href=\"https://example.org/cat.png\">cat</a>");
+}
@@ -0,0 +1,352 @@
+use determinant::client::{DataHolder, FileKind, MessageContent, Presence,
+    Server, StateChange};
+use determinant::sync::{apply_messages, apply_sync};
+use json::JsonValue;
+
+const INITIAL: &str = include_str!("fixtures/initial_sync.json");
+const INCREMENTAL: &str = include_str!("fixtures/incremental_sync.json");
+const LIMITED: &str = include_str!("fixtures/limited_sync.json");
+const MESSAGES: &str = include_str!("fixtures/messages.json");
+const MESSAGE_TYPES: &str = include_str!("fixtures/message_types_sync.json");
+const RELATIONS: &str = include_str!("fixtures/relations_sync.json");
+const DM_INVITE: &str = include_str!("fixtures/dm_invite_sync.json");
+const LATE_RELATIONS: &str =
+    include_str!("fixtures/late_relations_sync.json");
+
+fn holder() -> DataHolder {
+    let mut holder = DataHolder::new(Server {
+        address: String::from("https://example.org"),
+    });
+    holder.user_id = String::from("@me:example.org");
+    holder
+}
+
+fn fixture(text: &str) -> JsonValue {
+    json::parse(text).unwrap()
+}
+
+fn bodies(holder: &DataHolder, room_id: &str) -> Vec<String> {
+    holder.rooms[room_id].messages.iter()
+        .filter(|msg| !matches!(msg.content, MessageContent::State(_)))
+        .map(|msg| msg.content.body().to_string())
+        .collect()
+}
+
+#[test]
+fn initial_sync_fills_rooms() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+
+    assert_eq!(holder.next_batch, "s72595_4483_1934");
+    assert_eq!(holder.rooms.len(), 2);
+
+    let general = &holder.rooms["!general:example.org"];
+    assert_eq!(general.name, "General");
+    assert_eq!(general.alias, "#general:example.org");
+    assert_eq!(general.members, ["@alice:example.org", "@me:example.org"]);
+    let names: Vec<&str> = general.mention_names.iter()
+        .map(|(name, _)| &name[..])
+        .collect();
+    assert_eq!(names, ["@alice:example.org", "@me:example.org", "Alice", "Me"]);
+    assert_eq!(general.prev_batch, "t34-23535_0_0");
+    assert_eq!(general.unread_msgs, 1);
+    assert_eq!(general.receipts["@me:example.org"], "$msg-1");
+    assert!(general.account_data["m.tag"]["tags"]["m.favourite"]
+        .is_object());
+    assert_eq!(bodies(&holder, "!general:example.org"), ["Hello", "Hi Alice"]);
+
+    assert_eq!(holder.users["@alice:example.org"].name, "Alice");
+    assert_eq!(holder.users["@me:example.org"].rooms.len(), 2);
+    assert_eq!(holder.room_invites, ["!party:example.org"]);
+    assert!(holder.account_data["m.direct"].is_object());
+}
+
+#[test]
+fn applying_twice_changes_nothing() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+    apply_sync(&mut holder, &fixture(INITIAL));
+
+    let general = &holder.rooms["!general:example.org"];
+    assert_eq!(general.members.len(), 2);
+    assert_eq!(bodies(&holder, "!general:example.org"), ["Hello", "Hi Alice"]);
+    assert_eq!(holder.room_invites.len(), 1);
+    assert_eq!(holder.users["@me:example.org"].rooms.len(), 2);
+
+    apply_sync(&mut holder, &fixture(INCREMENTAL));
+    apply_sync(&mut holder, &fixture(INCREMENTAL));
+    assert_eq!(bodies(&holder, "!general:example.org"),
+        ["Hello", "Hi Alice", "Bye!"]);
+}
+
+#[test]
+fn incremental_sync_merges_changes() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+    apply_sync(&mut holder, &fixture(INCREMENTAL));
+
+    assert_eq!(holder.next_batch, "s72596_4484_1935");
+
+    let general = &holder.rooms["!general:example.org"];
+    assert_eq!(bodies(&holder, "!general:example.org"),
+        ["Hello", "Hi Alice", "Bye!"]);
+    // Old state is kept, new state is applied on top.
+    assert_eq!(general.name, "General");
+    assert_eq!(general.topic, "Anything goes");
+    assert_eq!(general.members, ["@me:example.org"]);
+    assert_eq!(general.prev_batch, "t34-23535_0_0");
+    assert_eq!(general.unread_msgs, 0);
+    assert_eq!(general.typing, ["@bob:example.org"]);
+    assert_eq!(general.receipts["@me:example.org"], "$msg-3");
+    assert_eq!(general.account_data["m.fully_read"]["event_id"], "$msg-3");
+    assert!(general.account_data.contains_key("m.tag"));
+}
+
+#[test]
+fn joining_and_leaving_rooms() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+    apply_sync(&mut holder, &fixture(INCREMENTAL));
+
+    // The invite was accepted.
+    assert!(holder.room_invites.is_empty());
+    let party = &holder.rooms["!party:example.org"];
+    assert_eq!(party.members, ["@carol:example.org", "@me:example.org"]);
+
+    // The direct chat was left.
+    assert!(!holder.rooms.contains_key("!dm:example.org"));
+    assert!(!holder.users["@bob:example.org"].rooms
+        .contains(&String::from("!dm:example.org")));
+    assert!(!holder.users["@alice:example.org"].rooms
+        .contains(&String::from("!general:example.org")));
+}
+
+#[test]
+fn limited_timeline_drops_old_messages() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+    apply_sync(&mut holder, &fixture(LIMITED));
+
+    let general = &holder.rooms["!general:example.org"];
+    assert_eq!(bodies(&holder, "!general:example.org"),
+        ["Back after a long time"]);
+    assert_eq!(general.prev_batch, "t50-23600_0_0");
+    // Rooms missing from the response are not touched.
+    assert_eq!(general.members.len(), 2);
+    assert_eq!(bodies(&holder, "!dm:example.org"), ["Are you there?"]);
+}
+
+#[test]
+fn older_messages_are_prepended() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+    apply_messages(&mut holder, "!general:example.org", &fixture(MESSAGES));
+
+    let general = &holder.rooms["!general:example.org"];
+    assert_eq!(bodies(&holder, "!general:example.org"),
+        ["Oldest", "Second oldest", "Hello", "Hi Alice"]);
+    assert_eq!(general.prev_batch, "t20-23400_0_0");
+    assert_eq!(holder.users["@dave:example.org"].name, "Dave");
+    // Lazy loaded members are not added to the room.
+    assert_eq!(general.members.len(), 2);
+}
+
+#[test]
+fn message_types_are_parsed() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(MESSAGE_TYPES));
+
+    let contents: Vec<&MessageContent> = holder.rooms["!media:example.org"]
+        .messages.iter()
+        .map(|msg| &msg.content)
+        .collect();
+    assert_eq!(contents, [
+        &MessageContent::Notice(String::from("Build passed")),
+        &MessageContent::Emote(String::from("waves")),
+        &MessageContent::File {
+            kind: FileKind::Image,
+            name: String::from("cat.png"),
+            size: Some(31337),
+            url: String::from("mxc://example.org/cat"),
+        },
+        &MessageContent::Location {
+            body: String::from("Office"),
+            geo_uri: String::from("geo:42.88,-8.54"),
+        },
+        &MessageContent::Other(String::from("Lunch?")),
+    ]);
+
+    let messages = &holder.rooms["!media:example.org"].messages;
+    assert_eq!(messages[0].timestamp, 1633200000000);
+    assert_eq!(messages[1].timestamp, 1633200001000);
+}
+
+#[test]
+fn replies_edits_and_redactions_are_applied() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(RELATIONS));
+
+    let messages = &holder.rooms["!chat:example.org"].messages;
+    assert_eq!(messages.len(), 3);
+
+    assert_eq!(messages[1].reply_to, "$question");
+    assert_eq!(messages[1].content,
+        MessageContent::Text(String::from("See you there")));
+    assert!(messages[1].edited);
+    assert_eq!(messages[1].html, "See you <b>there</b>");
+    assert_eq!(messages[0].html, "");
+    assert!(!messages[0].edited);
+
+    assert_eq!(messages[2].content, MessageContent::Redacted);
+}
+
+#[test]
+fn edits_wait_for_older_messages() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+    apply_sync(&mut holder, &fixture(LATE_RELATIONS));
+    assert_eq!(bodies(&holder, "!general:example.org"),
+        ["Hello", "Hi Alice"]);
+
+    apply_messages(&mut holder, "!general:example.org", &fixture(MESSAGES));
+    let general = &holder.rooms["!general:example.org"];
+    // The newest edit wins even if it came first, the forged one is ignored.
+    assert_eq!(bodies(&holder, "!general:example.org"),
+        ["The oldest", "Second oldest", "Hello", "Hi Alice"]);
+    assert!(general.messages[0].edited);
+    assert!(!general.messages[1].edited);
+    assert!(general.pending_relations.is_empty());
+}
+
+#[test]
+fn reactions_wait_for_older_messages() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+    apply_sync(&mut holder, &fixture(LATE_RELATIONS));
+    apply_sync(&mut holder, &fixture(LATE_RELATIONS));
+    apply_messages(&mut holder, "!general:example.org", &fixture(MESSAGES));
+
+    // The reaction removed before the message was loaded is not shown.
+    let second = &holder.rooms["!general:example.org"].messages[1];
+    assert_eq!(second.reaction_counts(), [("❤️", 1)]);
+    assert_eq!(second.reactions[0].sender, "@me:example.org");
+}
+
+#[test]
+fn reactions_are_aggregated() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(RELATIONS));
+    apply_sync(&mut holder, &fixture(RELATIONS));
+
+    let question = &holder.rooms["!chat:example.org"].messages[0];
+    assert_eq!(question.reaction_counts(), [("👍", 2), ("👀", 1)]);
+    assert!(question.reactions.iter()
+        .any(|r| r.sender == "@me:example.org" && r.event_id == "$thumbs-me"));
+}
+
+#[test]
+fn state_changes_are_kept_in_the_timeline() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+    apply_sync(&mut holder, &fixture(INCREMENTAL));
+    apply_sync(&mut holder, &fixture(INCREMENTAL));
+
+    let changes: Vec<&StateChange> = holder.rooms["!general:example.org"]
+        .messages.iter()
+        .filter_map(|msg| match &msg.content {
+            MessageContent::State(change) => Some(change),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(changes, [
+        &StateChange::Left,
+        &StateChange::Topic(String::from("Anything goes")),
+    ]);
+}
+
+#[test]
+fn rooms_are_named() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+
+    assert_eq!(holder.room_name("!general:example.org"), "General");
+    assert_eq!(holder.room_name("!dm:example.org"), "Bob");
+
+    holder.rooms.get_mut("!general:example.org").unwrap().name.clear();
+    assert_eq!(holder.room_name("!general:example.org"),
+        "#general:example.org");
+
+    // Without a name or alias the members are used.
+    let general = holder.rooms.get_mut("!general:example.org").unwrap();
+    general.alias.clear();
+    assert_eq!(holder.room_name("!general:example.org"), "Alice");
+
+    apply_sync(&mut holder, &fixture(INCREMENTAL));
+    let general = holder.rooms.get_mut("!general:example.org").unwrap();
+    general.joined_count = 1;
+    assert_eq!(holder.room_name("!general:example.org"), "Empty room");
+}
+
+#[test]
+fn invites_keep_a_preview() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+
+    let party = &holder.invited_rooms["!party:example.org"];
+    assert_eq!(party.inviter, "@carol:example.org");
+    assert_eq!(holder.room_name("!party:example.org"), "Party planning");
+
+    // Accepting the invite drops the preview.
+    apply_sync(&mut holder, &fixture(INCREMENTAL));
+    assert!(holder.invited_rooms.is_empty());
+}
+
+#[test]
+fn unnamed_invites_are_named_after_the_inviter() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(DM_INVITE));
+
+    let secret = &holder.invited_rooms["!secret:example.org"];
+    assert_eq!(secret.inviter, "@carol:example.org");
+    assert_eq!(holder.room_name("!secret:example.org"), "Carol");
+}
+
+#[test]
+fn rooms_are_sorted() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+
+    // Favourites go first even if other rooms are more recent.
+    assert_eq!(holder.sorted_rooms(),
+        ["!general:example.org", "!dm:example.org"]);
+    assert_eq!(holder.rooms["!dm:example.org"].highlight_msgs, 1);
+
+    let general = holder.rooms.get_mut("!general:example.org").unwrap();
+    general.account_data.insert(String::from("m.tag"), json::object! {
+        "tags": { "m.lowpriority": {} }
+    });
+    assert_eq!(holder.sorted_rooms(),
+        ["!dm:example.org", "!general:example.org"]);
+
+    // The rest are sorted by their newest event.
+    apply_sync(&mut holder, &fixture(INCREMENTAL));
+    holder.rooms.get_mut("!general:example.org").unwrap().account_data
+        .clear();
+    assert_eq!(holder.sorted_rooms(),
+        ["!party:example.org", "!general:example.org"]);
+}
+
+#[test]
+fn presence_is_tracked() {
+    let mut holder = holder();
+    apply_sync(&mut holder, &fixture(INITIAL));
+
+    let bob = &holder.users["@bob:example.org"];
+    assert_eq!(bob.presence, Presence::Unavailable);
+    assert_eq!(bob.last_active_ago, Some(420000));
+    assert_eq!(bob.status_msg, "Out for lunch");
+    assert_eq!(holder.users["@alice:example.org"].presence,
+        Presence::Offline);
+    assert_eq!(holder.dm_partner("!dm:example.org"), Some("@bob:example.org"));
+    assert_eq!(holder.dm_partner("!general:example.org"), None);
+}
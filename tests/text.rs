@@ -0,0 +1,126 @@
+use determinant::config::Config;
+use determinant::text::{color_mentions, day_separator, message_time,
+    split_words, wrap};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+
+fn text(lines: &[Spans]) -> Vec<String> {
+    lines.iter()
+        .map(|line| line.0.iter().map(|span| span.content.as_ref()).collect())
+        .collect()
+}
+
+fn wrap_text(line: &str, width: usize) -> Vec<String> {
+    text(&wrap(Spans::from(line.to_string()), width))
+}
+
+#[test]
+fn words_and_spaces_are_split() {
+    assert_eq!(split_words("one  two\tthree "),
+        ["one", "  ", "two", "\t", "three", " "]);
+    assert!(split_words("").is_empty());
+}
+
+#[test]
+fn spaces_are_dropped_at_line_breaks() {
+    assert_eq!(wrap_text("hello world again", 11), ["hello world", "again"]);
+    assert_eq!(wrap_text("hello   world", 5), ["hello", "world"]);
+    assert_eq!(wrap_text("ab cd", 3), ["ab", "cd"]);
+    assert_eq!(wrap_text("short", 10), ["short"]);
+}
+
+#[test]
+fn long_words_are_split() {
+    assert_eq!(wrap_text("abcdefgh", 3), ["abc", "def", "gh"]);
+    assert_eq!(wrap_text("hi abcdefgh", 4), ["hi a", "bcde", "fgh"]);
+}
+
+#[test]
+fn wide_characters_take_two_columns() {
+    assert_eq!(wrap_text("日本語です", 5), ["日本", "語で", "す"]);
+    assert_eq!(wrap_text("a 日本", 4), ["a", "日本"]);
+    assert_eq!(wrap_text("a 日本", 3), ["a", "日", "本"]);
+}
+
+#[test]
+fn spans_with_the_same_style_are_merged() {
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let lines = wrap(Spans::from(vec![
+        Span::styled("one ", bold),
+        Span::styled("two", bold),
+        Span::raw(" three"),
+    ]), 20);
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0].0.len(), 2);
+    assert_eq!(lines[0].0[0].content, "one two");
+    assert_eq!(lines[0].0[0].style, bold);
+    assert_eq!(lines[0].0[1].content, " three");
+}
+
+/// Parts of a line and their colour after colouring the mentions of Alice.
+fn mentions(line: &str) -> Vec<(String, Option<Color>)> {
+    let config = Config::parse("[colors]\nnicks = [\"red\"]\n").unwrap();
+    let alice = String::from("@alice:example.org");
+    let names = [
+        (alice.clone(), alice.clone()),
+        (String::from("Alice Smith"), alice.clone()),
+        (String::from("Alice"), alice),
+    ];
+    color_mentions(Spans::from(line.to_string()), &names, &config.colors).0
+        .into_iter()
+        .map(|span| (span.content.to_string(), span.style.fg))
+        .collect()
+}
+
+#[test]
+fn mentions_are_whole_words() {
+    let red = Some(Color::Red);
+    assert_eq!(mentions("Alice, hi"), [
+        (String::from("Alice"), red),
+        (String::from(", hi"), None),
+    ]);
+    assert_eq!(mentions("Alicebot and MrAlice"),
+        [(String::from("Alicebot and MrAlice"), None)]);
+    assert_eq!(mentions("ask @alice:example.org"), [
+        (String::from("ask "), None),
+        (String::from("@alice:example.org"), red),
+    ]);
+    // The longest name is used.
+    assert_eq!(mentions("Alice Smith."), [
+        (String::from("Alice Smith"), red),
+        (String::from("."), None),
+    ]);
+}
+
+/// Separators shown before messages sent at the given timestamps.
+fn separators(timestamps: &[u64], utc: bool) -> Vec<Option<String>> {
+    let mut last_day = None;
+    timestamps.iter()
+        .map(|timestamp| {
+            let time = message_time(*timestamp, utc).unwrap();
+            day_separator(&time, &mut last_day, "%Y-%m-%d")
+        })
+        .collect()
+}
+
+#[test]
+fn days_are_separated() {
+    // 2021-10-02 22:00, 23:30 and 2021-10-03 00:30 in UTC.
+    let timestamps = [1633212000000, 1633217400000, 1633221000000];
+    assert_eq!(separators(&timestamps, true), [
+        Some(String::from("── 2021-10-02 ──")),
+        None,
+        Some(String::from("── 2021-10-03 ──")),
+    ]);
+
+    // Five hours behind UTC the three messages are sent the same day. The
+    // time zone is read once, no other test uses the local time.
+    std::env::set_var("TZ", "Etc/GMT+5");
+    assert_eq!(separators(&timestamps, false), [
+        Some(String::from("── 2021-10-02 ──")),
+        None,
+        None,
+    ]);
+
+    assert!(message_time(0, true).is_none());
+}